@@ -4,15 +4,24 @@ mod action;
 mod browse;
 mod fetch;
 mod date_parse;
+mod search;
+mod doctor;
+mod export;
+mod markdown;
 
-use std::io;
+use std::{io, process, str::FromStr};
 use atty::Stream;
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
 use chrono::NaiveDate;
 use chrono::offset::Local;
-use super::data;
-use super::jira;
+use xfel_worklog::{config, data};
+use xfel_worklog::data::field_filter::FieldFilter;
+use xfel_worklog::data::query::Query;
+use xfel_worklog::data::tag_expr::TagExpr;
+use xfel_worklog::jira;
+use xfel_worklog::Result;
 use log::info;
+use regex::Regex;
 
 fn default_start_date() -> &'static str {
     let today = Local::today().format("%Y-%m-%d");
@@ -25,7 +34,7 @@ fn stdin_path() -> Option<String> {
     if atty::is(Stream::Stdin) {
         return None;
     }
-    io::stdin().lines().nth(0).unwrap().ok()
+    io::stdin().lines().next().unwrap().ok()
 }
 
 fn stdin_path_multiple() -> Option<Vec<String>> {
@@ -40,6 +49,11 @@ fn stdin_path_multiple() -> Option<Vec<String>> {
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    /// Profile from config file to use, defaults to its `default_profile`.
+    /// Env variables (DIARY_*, JIRA_*) override profile values.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands
 }
@@ -47,27 +61,34 @@ pub struct Args {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Query for elements
-    /// Two env variables are involved here:
+    /// These env variables (or their profile counterparts) are involved here:
     /// 
-    /// - DIARY_ROOT: defines root directory of diary files
+    /// - DIARY_ROOT (root): defines root directory of diary files
+    ///
+    /// - DIARY_INCLUDE_ARCHIVE (include_archive): include (or not, this is default) files under DIARY_ROOT/_archive directory
     ///
-    /// - DIARY_INCLUDE_ARCHIVE: include (or not, this is default) files under DIARY_ROOT/_archive directory
+    /// - DIARY_DAY_START (day_start): hour at which days start on table output, sessions crossing it are split
     Query {
-        /// Filter by tags: 'my tag'
-        #[arg(short, long)]
-        tags: Option<Vec<String>>,
-
-        /// Filter by path, it could by any part of the diary's path
-        /// It will default to received stdin if any. 
-        /// When stdin contains more than a line, it will consider
-        /// only the first line of it.
-        #[arg(short, long)]
-        path: Option<String>,
+        #[command(flatten)]
+        filters: Filters,
 
         /// Output only paths, similar to browse command
         #[arg(short, long, value_enum, default_value_t = QueryOutput::Table)]
         output: QueryOutput,
 
+        /// Extra frontmatter fields to show as columns on table output
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        columns: Vec<MetaColumn>,
+
+        /// Count only sessions fully inside the dates range, instead of
+        /// clipping those crossing its bounds to it
+        #[arg(long, default_value_t = false)]
+        whole_sessions: bool,
+
+        /// Show durations as decimal hours (7.50) on timesheet output
+        #[arg(long, default_value_t = false)]
+        decimal_hours: bool,
+
         /// In ISO format: '2020-01-01' or one of the following: today, yesterday, month (current month), biweekly (current month's half), friday (last friday)
         #[arg(
             default_value = default_start_date(), 
@@ -91,12 +112,67 @@ pub enum Commands {
 
     /// Similar to query but this will return a list of matched paths
     Browse {
-        /// Return only active paths. This is, files with an unterminated
-        /// worklog.
-        #[arg(short, long, default_value_t = false)]
-        active: bool
+        #[command(flatten)]
+        filters: Filters
     },
 
+    /// Search documents' body showing matching lines
+    Search {
+        /// Regular expression matched against each line of documents' body
+        #[arg(value_parser = Regex::new)]
+        pattern: Regex,
+
+        #[command(flatten)]
+        filters: Filters,
+
+        /// Lines of context to show around each match
+        #[arg(short = 'C', long, default_value_t = 2)]
+        context: usize,
+
+        /// Only documents with worklog from this date. Same format as query's start_date
+        #[arg(value_parser = date_parse::input)]
+        start_date: Option<NaiveDate>,
+
+        /// Only documents with worklog until this date. Same format as query's end_date
+        #[arg(value_parser = date_parse::input)]
+        end_date: Option<NaiveDate>
+    },
+
+    /// Check diary files for worklog problems
+    ///
+    /// Reports invalid frontmatters, unparseable worklog entries, entries
+    /// ending before they start, overlapping entries (within a document or
+    /// across documents) and documents with more than one open entry.
+    /// Exits with 1 when something is found.
+    Doctor {
+        /// Check files under DIARY_ROOT/_archive directory too, regardless
+        /// of DIARY_INCLUDE_ARCHIVE.
+        #[arg(long, default_value_t = false)]
+        include_archive: bool,
+
+        /// Fix what can be fixed before checking: normalize entries format,
+        /// close stale open entries, drop zero-length entries and split
+        /// overlapping ones. A diff is shown for each file, asking before
+        /// writing it.
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+
+        /// Hours after which an open entry is stale, it gets closed that
+        /// long after its start (or when the next entry starts)
+        #[arg(long, default_value_t = 8, requires = "fix")]
+        close_after: u32,
+
+        /// Write fixes without asking
+        #[arg(short, long, default_value_t = false, requires = "fix")]
+        yes: bool
+    },
+
+    /// Rebuild the index of parsed frontmatters from scratch
+    ///
+    /// Index is updated on every command for changed files, this is only
+    /// needed if it gets out of sync somehow.
+    Reindex,
+
     /// Fetch element from Jira.
     Fetch {
         /// Issue key
@@ -108,6 +184,143 @@ pub enum Commands {
     }
 }
 
+/// Filters shared by commands listing documents, a document must match all
+/// of them.
+#[derive(ClapArgs, Debug)]
+pub struct Filters {
+    /// Filter by tags: 'my tag'
+    #[arg(short, long)]
+    pub tags: Option<Vec<String>>,
+
+    /// Filter by a tags expression: 'clientA and (bug or incident) and not internal'.
+    /// Operators are and, or, not and parentheses. Quote tags with spaces: "'my tag' or other"
+    #[arg(short = 'q', long = "tag-query", value_parser = TagExpr::from_str)]
+    pub tag_query: Option<TagExpr>,
+
+    /// Filter by path, it could by any part of the diary's path
+    /// On query, it will default to received stdin if any.
+    /// When stdin contains more than a line, it will consider
+    /// only the first line of it.
+    #[arg(short, long)]
+    pub path: Option<String>,
+
+    /// Filter by a regular expression matched against each line of
+    /// documents' body (what follows frontmatter)
+    #[arg(long, value_parser = Regex::new)]
+    pub grep: Option<Regex>,
+
+    /// Filter by author, any part of it ignoring case
+    #[arg(long)]
+    pub author: Option<String>,
+
+    /// Return only documents with an estimate
+    #[arg(long, default_value_t = false, conflicts_with = "no_estimate")]
+    pub has_estimate: bool,
+
+    /// Return only documents without an estimate
+    #[arg(long, default_value_t = false)]
+    pub no_estimate: bool,
+
+    /// Return only documents whose frontmatter date is this one or later.
+    /// Same format as query's start_date
+    #[arg(long, value_parser = date_parse::input)]
+    pub doc_date_from: Option<NaiveDate>,
+
+    /// Return only documents whose frontmatter date is this one or earlier.
+    /// Same format as query's start_date
+    #[arg(long, value_parser = date_parse::input)]
+    pub doc_date_to: Option<NaiveDate>,
+
+    /// Filter by any frontmatter field: 'status=done', 'priority!=low' or
+    /// 'sprint~2022-4.*' (regular expression). On lists, it matches when
+    /// any item does (none for !=). Can be repeated
+    #[arg(short, long = "where", value_parser = FieldFilter::from_str)]
+    pub r#where: Vec<FieldFilter>,
+
+    /// Sort documents by a frontmatter field. Documents without it go last
+    #[arg(long)]
+    pub sort_by: Option<String>,
+
+    /// Return only active documents. This is, files with an unterminated
+    /// worklog.
+    #[arg(short, long, default_value_t = false)]
+    pub active: bool,
+
+    /// Include files under DIARY_ROOT/_archive directory, regardless of
+    /// DIARY_INCLUDE_ARCHIVE.
+    #[arg(long, default_value_t = false)]
+    pub include_archive: bool
+}
+
+impl Filters {
+    pub fn query(&self) -> Query {
+        let mut query = Query::new();
+        if let Some(tags) = &self.tags {
+            query = query.tags(tags.clone());
+        }
+        if let Some(expr) = &self.tag_query {
+            query = query.tag_expr(expr.clone());
+        }
+        if let Some(path) = &self.path {
+            query = query.path(path);
+        }
+        if let Some(re) = &self.grep {
+            query = query.grep(re.clone());
+        }
+        if let Some(author) = &self.author {
+            query = query.author(author);
+        }
+        if self.has_estimate || self.no_estimate {
+            query = query.has_estimate(self.has_estimate);
+        }
+        if self.doc_date_from.is_some() || self.doc_date_to.is_some() {
+            query = query.doc_date_range(self.doc_date_from, self.doc_date_to);
+        }
+        for filter in &self.r#where {
+            query = query.field(filter.clone());
+        }
+        if let Some(field) = &self.sort_by {
+            query = query.sort_by(field);
+        }
+        if self.active {
+            query = query.active();
+        }
+        if self.include_archive {
+            query = query.include_archive(true);
+        }
+        query
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum MetaColumn {
+    Author,
+
+    /// Frontmatter's date
+    Date,
+
+    Estimate
+}
+
+impl MetaColumn {
+    pub fn header(&self) -> &'static str {
+        match self {
+            MetaColumn::Author => "Author",
+            MetaColumn::Date => "Doc date",
+            MetaColumn::Estimate => "Estimate"
+        }
+    }
+
+    pub fn value(&self, doc: &data::model::DiaryDoc) -> String {
+        let value = match self {
+            MetaColumn::Author => &doc.metadata.author,
+            MetaColumn::Date => &doc.metadata.date,
+            MetaColumn::Estimate => &doc.metadata.estimate
+        };
+        value.clone().unwrap_or_default()
+    }
+}
+
 #[derive(Copy, Clone, Debug, ValueEnum)]
 pub enum QueryOutput {
     /// It will output a table with files and time spent.
@@ -119,7 +332,25 @@ pub enum QueryOutput {
     /// It will output a table only with tags and time spent.
     /// Note that tags that looks like Jira tickets (EXAMPLE-123)
     /// will be filtered out from output (not from time sum)
-    Tags
+    Tags,
+
+    /// A JSON array with a record per session, day, doc and tag plus the
+    /// total.
+    Json,
+
+    /// Same records as json, one per line.
+    Ndjson,
+
+    /// Same records as json, with tags joined by ';'.
+    Csv,
+
+    /// Table and tags table as GitHub flavored markdown, with day and
+    /// grand totals.
+    Markdown,
+
+    /// A row per file (or Jira key) and a column per day in the dates
+    /// range, with row, day and grand totals.
+    Timesheet
 }
 
 #[derive(Subcommand, Debug)]
@@ -128,7 +359,12 @@ pub enum ActionKind {
     Start,
 
     /// It stops worklog, this is, it inserts a new stop timestamp for a document.
-    Stop,
+    Stop {
+        /// Note for this worklog entry, it will be used as comment when
+        /// syncing worklog with Jira.
+        #[arg(short, long)]
+        message: Option<String>
+    },
 
     /// It syncs worklogs entries with Jira.
     SyncWorklog,
@@ -137,15 +373,16 @@ pub enum ActionKind {
     Archive
 }
 
-fn stop_active_docs() {
-    for mut active_doc in data::query::active() {
+fn stop_active_docs() -> Result<()> {
+    for mut active_doc in Query::new().active().run()? {
         info!("Stopping active doc: {}", active_doc.path);
         active_doc.stop();
-        data::update_entry(active_doc);
+        data::update_entry(active_doc)?;
     }
+    Ok(())
 }
 
-fn print_paths(docs: Vec<data::model::DiaryDoc>) {
+fn print_paths(docs: &[data::model::DiaryDoc]) {
     let paths: Vec<String> = docs
         .iter()
         .map(|x| String::from(&x.path))
@@ -155,19 +392,43 @@ fn print_paths(docs: Vec<data::model::DiaryDoc>) {
     }
 }
 
-pub fn main() {
-    let cli = Args::parse();
+fn run(cli: &Args) -> Result<()> {
     match &cli.command {
-        Commands::Query { tags, path, start_date, end_date, output } => {
+        Commands::Query {
+            filters, start_date, end_date, output, columns, whole_sessions, decimal_hours
+        } => {
             let end_date_parsed: Option<NaiveDate> = if let Some(x) = end_date {
                 date_parse::input(x).ok()
             } else {
                 None
             };
-            query::run(tags, path, start_date, &end_date_parsed, output);
+            query::run(
+                filters, start_date, &end_date_parsed, *whole_sessions, output, columns,
+                *decimal_hours
+            )
         }
         Commands::Action { path, kind } => action::run(path, kind),
-        Commands::Browse { active } => browse::run(active),
-        Commands::Fetch { key, path } => fetch::run(key, path)
+        Commands::Browse { filters } => browse::run(filters),
+        Commands::Search { pattern, filters, context, start_date, end_date } => {
+            search::run(pattern, filters, *context, start_date, end_date)
+        }
+        Commands::Doctor { include_archive, fix, close_after, yes } => {
+            doctor::run(*include_archive, *fix, *close_after, *yes)
+        }
+        Commands::Fetch { key, path } => fetch::run(key, path),
+        Commands::Reindex => {
+            let count = data::reindex()?;
+            info!("Indexed {} documents", count);
+            Ok(())
+        }
+    }
+}
+
+pub fn main() {
+    let cli = Args::parse();
+    config::select_profile(cli.profile.clone());
+    if let Err(e) = run(&cli) {
+        eprintln!("{}", e);
+        process::exit(e.exit_code());
     }
 }
@@ -1,58 +1,66 @@
 use super::{data, ActionKind, stop_active_docs, stdin_path_multiple};
 use super::data::model::DiaryDoc;
+use super::Query;
 use super::jira;
+use xfel_worklog::Result;
 use log::{info, error};
 
-fn do_action(kind: &ActionKind, mut doc: DiaryDoc) {
+fn do_action(kind: &ActionKind, mut doc: DiaryDoc) -> Result<()> {
     match kind {
         ActionKind::Start => {
             if doc.is_active() {
                 error!("Requested doc is already active");
-                return;
+                return Ok(());
             }
-            stop_active_docs();
+            stop_active_docs()?;
             doc.start();
-            data::update_entry(doc);
+            data::update_entry(doc)?;
         }
-        ActionKind::Stop => {
+        ActionKind::Stop { message } => {
             if !doc.is_active() {
                 error!("Requested doc is not active");
-                return;
+                return Ok(());
             }
-            doc.stop();
-            data::update_entry(doc);
+            doc.stop_with_note(message.clone());
+            data::update_entry(doc)?;
         }
         ActionKind::SyncWorklog => {
             info!("Syncing worklogs for {}...", doc.path);
-            if let Err(e) = jira::sync_worklogs(doc) {
-                error!("{}", e);
-            };
+            jira::sync_worklogs(doc)?;
             info!("Finished");
         }
         ActionKind::Archive => {
             if doc.is_archive() {
                 error!("Selected doc is already archived");
-                return;
+                return Ok(());
             }
-            data::archive_entry(doc);
+            data::archive_entry(doc)?;
         }
     };
+    Ok(())
 }
 
-pub fn run(path: &Option<String>, kind: &ActionKind) {
-    let results = if let Some(p) = path  {
-        data::query::by_path(p)
+pub fn run(path: &Option<String>, kind: &ActionKind) -> Result<()> {
+    // Held until docs are written back, sync only reads them
+    let _lock = match kind {
+        ActionKind::SyncWorklog => None,
+        _ => Some(data::lock_diary()?)
+    };
+    let query = if let Some(p) = path  {
+        Query::new().path(p)
     } else {
-        data::query::by_path_multiple(&stdin_path_multiple().unwrap())
+        Query::new().exact_paths(stdin_path_multiple().unwrap_or_default())
     };
+    let results = query.run()?;
     let matched_docs = results.len();
 
     if matched_docs < 1 {
         error!("Path doesn't match any document");
-        return;
+        return Ok(());
     }
     info!("Query matched {} docs", matched_docs);
     for doc in results {
-        do_action(kind, doc);
+        do_action(kind, doc)?;
     }
+    Ok(())
 }
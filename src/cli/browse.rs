@@ -1,11 +1,7 @@
-use super::{data, print_paths};
+use xfel_worklog::Result;
+use super::{print_paths, Filters};
 
-pub fn run(active: &bool) {
-    let docs = if *active {
-        data::query::active()
-    } else {
-        data::query::all()
-    };
-    print_paths(docs);
+pub fn run(filters: &Filters) -> Result<()> {
+    print_paths(&filters.query().run()?);
+    Ok(())
 }
-
@@ -0,0 +1,53 @@
+use std::io::{self, Write};
+use std::process;
+use chrono::Duration;
+use log::info;
+use similar::TextDiff;
+use xfel_worklog::{config, data, Error, Result};
+use xfel_worklog::data::doctor;
+
+fn confirm(path: &str) -> Result<bool> {
+    print!("Apply changes to {}? [y/N] ", path);
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| Error::Io(String::from("stdin"), e))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn fix(include_archive: bool, close_after: Duration, yes: bool) -> Result<()> {
+    let _lock = data::lock_diary()?;
+    for doc in doctor::fix(include_archive, close_after)? {
+        let (current, fixed) = data::render_entry(&doc, true)?;
+        if current == fixed {
+            continue;
+        }
+        let diff = TextDiff::from_lines(&current, &fixed);
+        print!("{}", diff.unified_diff().header(&doc.path, &doc.path));
+        if yes || confirm(&doc.path)? {
+            data::normalize_entry(doc)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(
+    include_archive: bool, fix_problems: bool, close_after: u32, yes: bool
+) -> Result<()> {
+    let include_archive = include_archive
+        || config::diary()?.include_archive.unwrap_or(false);
+    if fix_problems {
+        fix(include_archive, Duration::hours(close_after.into()), yes)?;
+    }
+    let diagnostics = doctor::diagnose(include_archive)?;
+    for x in diagnostics.iter() {
+        println!("{}", x);
+    }
+    if diagnostics.is_empty() {
+        info!("No problems found");
+        return Ok(());
+    }
+    info!("{} problems found", diagnostics.len());
+    process::exit(1);
+}
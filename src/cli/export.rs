@@ -0,0 +1,89 @@
+use std::io;
+use serde::Serialize;
+use xfel_worklog::{Error, Result};
+use xfel_worklog::report::{Record, Report};
+
+// Every record kind flattened into the same columns, those not applying to
+// a kind are left empty.
+#[derive(Serialize, Default)]
+struct CsvRow {
+    r#type: &'static str,
+    date: Option<String>,
+    path: Option<String>,
+    jira_key: Option<String>,
+    tags: Option<String>,
+    tag: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    seconds: i64,
+    note: Option<String>
+}
+
+impl From<Record> for CsvRow {
+    fn from(record: Record) -> CsvRow {
+        match record {
+            Record::Session(x) => CsvRow {
+                r#type: "session",
+                date: Some(x.date),
+                path: Some(x.path),
+                jira_key: x.jira_key,
+                tags: Some(x.tags.join(";")),
+                start: Some(x.start),
+                end: Some(x.end),
+                seconds: x.seconds,
+                note: x.note,
+                ..Default::default()
+            },
+            Record::Day(x) => CsvRow {
+                r#type: "day",
+                date: Some(x.date),
+                seconds: x.seconds,
+                ..Default::default()
+            },
+            Record::Doc(x) => CsvRow {
+                r#type: "doc",
+                path: Some(x.path),
+                jira_key: x.jira_key,
+                seconds: x.seconds,
+                ..Default::default()
+            },
+            Record::Tag(x) => CsvRow {
+                r#type: "tag",
+                tag: Some(x.tag),
+                seconds: x.seconds,
+                ..Default::default()
+            },
+            Record::Total { seconds } => CsvRow {
+                r#type: "total",
+                seconds,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+fn stdout_error(e: impl ToString) -> Error {
+    Error::Io(String::from("stdout"), io::Error::other(e.to_string()))
+}
+
+pub fn print_json(report: &Report) -> Result<()> {
+    serde_json::to_writer_pretty(io::stdout(), &report.records())
+        .map_err(stdout_error)?;
+    println!();
+    Ok(())
+}
+
+pub fn print_ndjson(report: &Report) -> Result<()> {
+    for record in report.records() {
+        println!("{}", serde_json::to_string(&record).map_err(stdout_error)?);
+    }
+    Ok(())
+}
+
+pub fn print_csv(report: &Report) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for record in report.records() {
+        writer.serialize(CsvRow::from(record)).map_err(stdout_error)?;
+    }
+    writer.flush().map_err(stdout_error)
+}
@@ -1,21 +1,13 @@
 use super::jira;
-use super::data;
-use log::{info, error};
+use super::{data, Query};
+use xfel_worklog::Result;
+use log::info;
 
-pub fn run(key: &str, path: &Option<String>) {
-    let result = jira::fetch(key);
-    match result {
-        Ok(ticket) => {
-            let p = if let Some(path_str) = path {
-                Some(path_str.as_str())
-            } else {
-                None
-            }; 
-            data::create_entry(ticket, p);
-            let query_results = data::query::by_path(key);
-            let doc = query_results.first().unwrap();
-            info!("Created {}", doc.path);
-        }
-        Err(e) => error!("{}", e), 
+pub fn run(key: &str, path: &Option<String>) -> Result<()> {
+    let ticket = jira::fetch(key)?;
+    data::create_entry(ticket, path.as_deref())?;
+    if let Some(doc) = Query::new().path(key).run()?.first() {
+        info!("Created {}", doc.path);
     }
+    Ok(())
 }
@@ -0,0 +1,41 @@
+use chrono::Duration;
+use xfel_worklog::report::Report;
+use super::table::{duration_to_string, tags_rows, with_dates_rows};
+use super::MetaColumn;
+
+const RIGHT_ALIGNED: [&str; 4] = ["Duration", "Start", "End", "Total"];
+
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn print_row(cells: &[String]) {
+    let escaped: Vec<String> = cells.iter().map(|x| cell(x)).collect();
+    println!("| {} |", escaped.join(" | "));
+}
+
+// GitHub flavored table, with a last row holding the grand total
+fn print_table(headers: Vec<&str>, rows: Vec<Vec<String>>, total: &Duration) {
+    print_row(&headers.iter().map(|x| x.to_string()).collect::<Vec<String>>());
+    let align: Vec<String> = headers
+        .iter()
+        .map(|x| String::from(if RIGHT_ALIGNED.contains(x) { "---:" } else { "---" }))
+        .collect();
+    println!("| {} |", align.join(" | "));
+    for row in rows {
+        print_row(&row);
+    }
+    let mut last = vec![String::from(""); headers.len()];
+    last[0] = String::from("**Total**");
+    last[headers.len() - 1] = format!("**{}**", duration_to_string(total));
+    print_row(&last);
+}
+
+/// Dated table followed by the tags one.
+pub fn print(report: &Report, columns: &[MetaColumn]) {
+    let (headers, rows) = with_dates_rows(report, columns);
+    print_table(headers, rows, &report.total);
+    println!();
+    let (headers, rows) = tags_rows(report);
+    print_table(headers, rows, &report.total);
+}
@@ -1,41 +1,63 @@
 use chrono::NaiveDate;
-use super::{data, table, QueryOutput, stdin_path, print_paths};
+use xfel_worklog::{config, Result};
+use xfel_worklog::report::Report;
+use super::{export, markdown, table, Filters, MetaColumn, QueryOutput, stdin_path, print_paths};
 
 pub fn run(
-    tags: &Option<Vec<String>>,
-    path: &Option<String>,
+    filters: &Filters,
     start_date: &Option<NaiveDate>,
     end_date: &Option<NaiveDate>,
-    output: &QueryOutput
-) {
-    let received_path = if let Some(p) = path {
-        String::from(p)
-    } else if let Some(p) = stdin_path() {
-        String::from(p)
-    } else {
-        String::from("")
-    };
-    let results = if let Some(t) = tags {
-        if let Some(st) = start_date {
-            data::query::by_tags_and_date(t.clone(), &st, &end_date)
-        } else {
-            data::query::by_tags(t.clone())
-        }
-    } else if !received_path.is_empty() {
-        if let Some(st) = start_date {
-            data::query::by_path_and_date(&received_path, &st, &end_date)
-        } else {
-            data::query::by_path(&received_path)
+    whole_sessions: bool,
+    output: &QueryOutput,
+    columns: &[MetaColumn],
+    decimal_hours: bool
+) -> Result<()> {
+    let mut query = filters.query();
+    if filters.path.is_none() {
+        if let Some(p) = stdin_path().filter(|x| !x.is_empty()) {
+            query = query.path(&p);
         }
-    } else if let Some(st) = start_date {
-        data::query::by_date(&st, &end_date)
-    } else {
-        data::query::all()
-    };
+    }
+    let day_start = config::diary()?.day_start.unwrap_or(0);
+    if let Some(st) = start_date {
+        query = query
+            .date_range(*st, *end_date)
+            .day_start(day_start)
+            .whole_sessions(whole_sessions);
+    }
+    let results = query.run()?;
+    let report = Report::new(&results, day_start);
     match output {
-        QueryOutput::Table => table::print_with_dates(results),
-        QueryOutput::Paths => print_paths(results),
-        QueryOutput::Tags => table::print_tags(results)
+        QueryOutput::Table => table::print_with_dates(&report, columns),
+        QueryOutput::Paths => print_paths(&results),
+        QueryOutput::Tags => table::print_tags(&report),
+        QueryOutput::Json => export::print_json(&report)?,
+        QueryOutput::Ndjson => export::print_ndjson(&report)?,
+        QueryOutput::Csv => export::print_csv(&report)?,
+        QueryOutput::Markdown => markdown::print(&report, columns),
+        QueryOutput::Timesheet => {
+            let days = timesheet_days(&report, start_date, end_date);
+            table::print_timesheet(&report, &days, decimal_hours)
+        }
     };
+    Ok(())
 }
 
+/// Every day from the start date to the end date, widened to any day with
+/// time in the report so row and grand totals match the columns shown.
+fn timesheet_days(
+    report: &Report, start_date: &Option<NaiveDate>, end_date: &Option<NaiveDate>
+) -> Vec<NaiveDate> {
+    let first_day = report.days.first().map(|x| x.date);
+    let last_day = report.days.last().map(|x| x.date);
+    let first = start_date.iter().chain(first_day.iter()).min().copied();
+    let last = end_date.iter().chain(last_day.iter()).max().copied();
+    match (first, last) {
+        (Some(first), Some(last)) => first
+            .iter_days()
+            .take_while(|x| *x <= last)
+            .collect(),
+        (Some(first), None) => vec!(first),
+        _ => vec!()
+    }
+}
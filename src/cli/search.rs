@@ -0,0 +1,33 @@
+use chrono::NaiveDate;
+use regex::Regex;
+use xfel_worklog::Result;
+use super::{data, Filters};
+
+// Output follows grep's: `path:line:text` for matching lines,
+// `path-line-text` for context and `--` between hunks.
+pub fn run(
+    pattern: &Regex,
+    filters: &Filters,
+    context: usize,
+    start_date: &Option<NaiveDate>,
+    end_date: &Option<NaiveDate>
+) -> Result<()> {
+    let mut query = filters.query().grep(pattern.clone());
+    if let Some(st) = start_date {
+        query = query.date_range(*st, *end_date);
+    }
+    let mut first = true;
+    for doc in query.run()? {
+        for hunk in data::search::search_doc(&doc, pattern, context)? {
+            if !first {
+                println!("--");
+            }
+            first = false;
+            for (n, text, matched) in hunk.lines {
+                let sep = if matched { ':' } else { '-' };
+                println!("{}{}{}{}{}", doc.path, sep, n, sep, text);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,83 +1,18 @@
-use std::{collections::HashMap, ops::Range};
-use lazy_static::lazy_static;
-use regex::Regex;
-use chrono::{Duration, NaiveDate, NaiveDateTime};
+use std::collections::{BTreeMap, HashMap};
+use chrono::{Duration, NaiveDate};
 use tabled::locator::ByColumnName;
-use super::data::model::DiaryDoc;
-use tabled::object::Rows;
+use super::data::model::{is_jira_key, DiaryDoc};
+use super::MetaColumn;
+use xfel_worklog::report::{Report, Session};
+use tabled::object::{Columns, Rows};
 use tabled::builder::Builder;
 use tabled::{Style, Modify, Border, Panel, Alignment};
 
-struct PrintWithDatesData {
-    dates: HashMap<NaiveDate, Vec<Vec<String>>>,
-    durations: HashMap<NaiveDate, Duration>,
-    total: Duration
-}
-
-impl PrintWithDatesData {
-    pub fn new(results: Vec<DiaryDoc>) -> PrintWithDatesData {
-        let mut inst = PrintWithDatesData {
-            dates: HashMap::new(),
-            durations: HashMap::new(),
-            total: Duration::seconds(0)
-        };
-        let zero = Duration::seconds(0);
-        let mut worklogs: HashMap<NaiveDateTime, (Duration, Vec<String>)> = HashMap::new();
-
-        for doc in results.iter() {
-            for range in doc.worklog_range().iter() {
-                worklogs.insert(
-                    range.start, 
-                    PrintWithDatesData::doc_row(fname(doc), range)
-                );
-            }
-        }
-
-        let mut sorted_keys: Vec<&NaiveDateTime> = worklogs.keys().collect();
-        sorted_keys.sort();
-        for k in sorted_keys {
-            let mut empty = vec!();
-            let key = k.date();
-            let value: &mut Vec<Vec<String>> = inst.dates
-                .get_mut(&key)
-                .unwrap_or(&mut empty);
-            let (partial, row) = worklogs.get(k).unwrap();
-            value.push(row.to_vec());
-
-            let final_value = value.to_vec();
-            inst.dates.insert(key.clone(), final_value);
-
-            let this_date_duration = inst.durations.get(&key).unwrap_or(&zero);
-            inst.durations.insert(key, *this_date_duration + *partial);
-            inst.total  = inst.total + *partial;
-        }
-
-        inst
-    }
-
-    fn doc_row(ticket: String, range: &Range<NaiveDateTime>) -> (Duration, Vec<String>) {
-        let partial = range.end - range.start;
-        (
-            partial,
-            vec![
-                ticket,
-                range.start.format("%H:%M").to_string(),
-                range.end.format("%H:%M").to_string(),
-                duration_to_string(&partial),
-                String::from("")
-            ]
-        )
-    }
-
-}
-
-fn duration_to_string(duration: &Duration) -> String {
-    String::from(
-        format!(
-            "{}h {:02}m", 
-            duration.num_hours(), 
-            duration.num_minutes() - duration.num_hours() * 60
-        )
+pub fn duration_to_string(duration: &Duration) -> String {
+    format!(
+        "{}h {:02}m",
+        duration.num_hours(),
+        duration.num_minutes() - duration.num_hours() * 60
     )
 }
 
@@ -85,24 +20,6 @@ fn fname(doc: &DiaryDoc) -> String {
     String::from(doc.path.split("/").last().unwrap())
 }
 
-// This method will return:
-// - Rows from doc's worklogs
-// - Accumulated duration from all those rows
-fn ranges(doc: &DiaryDoc) -> (String, Duration) {
-    let mut duration_acc = Duration::seconds(0);
-    let rows = doc.worklog_range()
-        .iter()
-        .map(|y| {
-            let diff = y.end - y.start;
-            let dur = duration_to_string(&(y.end - y.start));
-            duration_acc = duration_acc + diff;
-            format!("{} -> {}: {}", y.start, y.end, dur)
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
-    (rows, duration_acc)
-}
-
 fn do_print(builder: Builder, total: &Duration) {
     let mut table = builder.build();
     table.with(Style::psql());
@@ -134,77 +51,169 @@ fn do_print(builder: Builder, total: &Duration) {
             .with(Alignment::center())
             .with(Border::empty().bottom('=').top('='))
     );
-    let footer_msg = format!("Total: {}", duration_to_string(&total));
+    let footer_msg = format!("Total: {}", duration_to_string(total));
     table.with(Panel::footer(footer_msg));
     table.with(Modify::new(Rows::last()).with(Alignment::right()));
 
     println!("{}", table);
 }
 
-fn looks_like_ticket(tag: String) -> bool {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^[A-Z0-9]*-[0-9]*$").unwrap();
-    }
-    RE.is_match(&tag)
+fn session_row(session: &Session, columns: &[MetaColumn]) -> Vec<String> {
+    let mut row = vec![String::from(""), fname(session.doc)];
+    row.extend(columns.iter().map(|x| x.value(session.doc)));
+    row.extend([
+        session.entry.start.format("%H:%M").to_string(),
+        session.entry.end.unwrap().format("%H:%M").to_string(),
+        duration_to_string(&session.duration()),
+        session.entry.note.clone().unwrap_or_default(),
+        String::from("")
+    ]);
+    row
 }
 
-fn print_with_dates_add_records(
-    builder: &mut Builder,
-    dates: &HashMap<NaiveDate, Vec<Vec<String>>>,
-    dates_duration: &HashMap<NaiveDate, Duration>
-) {
-    let mut sorted_dates = dates.keys().collect::<Vec<&NaiveDate>>();
-    sorted_dates.sort();
-    for key in sorted_dates.iter()  {
-        let mut first = vec![String::from(""); 6];
-        first[0] = key.to_string();
-        builder.add_record(first);
-
-        for x in dates.get(key).unwrap().iter() {
-            let mut row = vec![String::from("")];
-            row.extend_from_slice(&x);
-            builder.add_record(row);
+/// Header and rows of the dated table: for each day a row with the date,
+/// its sessions and its total.
+pub fn with_dates_rows(
+    report: &Report, columns: &[MetaColumn]
+) -> (Vec<&'static str>, Vec<Vec<String>>) {
+    let mut headers = vec!("Date", "Ticket");
+    headers.extend(columns.iter().map(|x| x.header()));
+    headers.extend(["Start", "End", "Duration", "Note", "Total"]);
+    let width = headers.len();
+
+    let mut rows = vec!();
+    for day in report.days.iter() {
+        let mut first = vec![String::from(""); width];
+        first[0] = day.date.to_string();
+        rows.push(first);
+
+        for session in day.sessions.iter() {
+            rows.push(session_row(session, columns));
         }
 
-        let mut last = vec![String::from(""); 6];
-        last[5] = duration_to_string(dates_duration.get(key).unwrap());
-        builder.add_record(last);
+        let mut last = vec![String::from(""); width];
+        last[width - 1] = duration_to_string(&day.total);
+        rows.push(last);
     }
+    (headers, rows)
 }
 
-pub fn print_with_dates(results: Vec<DiaryDoc>) {
+/// Header and rows of the tags table. Tags looking like Jira tickets are
+/// left out, their time is still part of the total.
+pub fn tags_rows(report: &Report) -> (Vec<&'static str>, Vec<Vec<String>>) {
+    let rows = report.tags
+        .iter()
+        .filter(|x| !is_jira_key(&x.tag))
+        .map(|x| vec!(x.tag.to_string(), duration_to_string(&x.total)))
+        .collect();
+    (vec!("Tag", "Duration"), rows)
+}
+
+fn print_rows(headers: Vec<&str>, rows: Vec<Vec<String>>, total: &Duration) {
     let mut builder = Builder::default();
-    builder.set_columns(
-        vec!("Date", "Ticket", "Start", "End", "Duration", "Total")
-    );
+    builder.set_columns(headers);
+    for row in rows {
+        builder.add_record(row);
+    }
+    do_print(builder, total);
+}
 
-    let data = PrintWithDatesData::new(results);
-    print_with_dates_add_records(&mut builder, &data.dates, &data.durations);
+pub fn print_with_dates(report: &Report, columns: &[MetaColumn]) {
+    let (headers, rows) = with_dates_rows(report, columns);
+    print_rows(headers, rows, &report.total);
+}
 
-    do_print(builder, &data.total);
+pub fn print_tags(report: &Report) {
+    let (headers, rows) = tags_rows(report);
+    print_rows(headers, rows, &report.total);
 }
 
-pub fn print_tags(results: Vec<DiaryDoc>) {
-    let mut builder = Builder::default();
-    builder.set_columns(vec!("Tag", "Duration"));
-    let mut total = Duration::seconds(0);
-    let mut tag_map: HashMap<String, Duration> = HashMap::new();
-    for x in results.iter() {
-        let (_, partial) = ranges(x);
-        for tag in x.metadata.tags.iter() {
-            let key = tag.to_string();
-            let default = Duration::seconds(0);
-            let current = tag_map.get(&key).unwrap_or(&default);
-            tag_map.insert(key, *current + partial);
+fn hours_to_string(duration: &Duration, decimal_hours: bool) -> String {
+    if decimal_hours {
+        format!("{:.2}", duration.num_seconds() as f64 / 3600.0)
+    } else {
+        duration_to_string(duration)
+    }
+}
+
+/// Header and rows of the timesheet: a row per Jira key, adding up every
+/// doc named after it, or per doc for the rest (named after its file, or
+/// its path when another one has the same name), and a column per day in
+/// `days`, then a row with day totals. Days without time are left blank.
+pub fn timesheet_rows(
+    report: &Report, days: &[NaiveDate], decimal_hours: bool
+) -> (Vec<String>, Vec<Vec<String>>) {
+    let cell = |x: Option<&Duration>| x
+        .filter(|x| !x.is_zero())
+        .map(|x| hours_to_string(x, decimal_hours))
+        .unwrap_or_default();
+
+    let mut headers = vec!(String::from("Ticket"));
+    headers.extend(days.iter().map(|x| x.format("%a %m-%d").to_string()));
+    headers.push(String::from("Total"));
+
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for x in report.docs.iter().filter(|x| x.doc.jira_key().is_none()) {
+        *names.entry(fname(x.doc)).or_default() += 1;
+    }
+    let label = |doc: &DiaryDoc| doc.jira_key().unwrap_or_else(|| {
+        let name = fname(doc);
+        if names[&name] > 1 { doc.path.to_string() } else { name }
+    });
+
+    // Label, time by day and total of each row, in first doc order
+    let mut by_label: Vec<(String, BTreeMap<NaiveDate, Duration>, Duration)> = vec!();
+    for x in report.docs.iter() {
+        let name = label(x.doc);
+        let idx = match by_label.iter().position(|(l, _, _)| *l == name) {
+            Some(i) => i,
+            None => {
+                by_label.push((name, BTreeMap::new(), Duration::zero()));
+                by_label.len() - 1
+            }
+        };
+        let (_, by_day, total) = &mut by_label[idx];
+        for (day, duration) in x.days.iter() {
+            let acc = by_day.entry(*day).or_insert_with(Duration::zero);
+            *acc = *acc + *duration;
         }
-        total = total + partial;
+        *total = *total + x.total;
     }
-    tag_map
+    let mut rows: Vec<Vec<String>> = by_label
         .iter()
-        .filter(|x| !looks_like_ticket(String::from(x.0)))
-        .map(|x| vec!(String::from(x.0), duration_to_string(x.1)))
-        .for_each(|x| {
-            builder.add_record(x);
-        });
-    do_print(builder, &total);
+        .map(|(name, by_day, total)| {
+            let mut row = vec!(name.to_string());
+            row.extend(days.iter().map(|day| cell(by_day.get(day))));
+            row.push(hours_to_string(total, decimal_hours));
+            row
+        })
+        .collect();
+
+    let mut totals = vec!(String::from("Total"));
+    totals.extend(days.iter().map(|day| cell(
+        report.days.iter().find(|x| x.date == *day).map(|x| &x.total)
+    )));
+    totals.push(hours_to_string(&report.total, decimal_hours));
+    rows.push(totals);
+    (headers, rows)
+}
+
+pub fn print_timesheet(report: &Report, days: &[NaiveDate], decimal_hours: bool) {
+    let (headers, rows) = timesheet_rows(report, days, decimal_hours);
+    let mut builder = Builder::default();
+    builder.set_columns(headers);
+    for row in rows {
+        builder.add_record(row);
+    }
+    let mut table = builder.build();
+    table
+        .with(Style::psql())
+        .with(Modify::new(Columns::new(1..)).with(Alignment::right()))
+        .with(
+            Modify::new(Rows::first())
+                .with(Alignment::center())
+                .with(Border::empty().bottom('=').top('='))
+        )
+        .with(Modify::new(Rows::last()).with(Border::empty().top('-').bottom('=')));
+    println!("{}", table);
 }
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, env, fs, io, path::PathBuf, sync::OnceLock};
+use serde::Deserialize;
+use crate::error::{Error, Result};
+
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
+/// Where diary files live.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiaryConfig {
+    pub root: String,
+    pub include_archive: Option<bool>,
+
+    /// Hour (0-23) at which a day starts on daily reports, for people
+    /// working past midnight.
+    pub day_start: Option<u32>
+}
+
+/// Jira credentials.
+#[derive(Deserialize, Debug, Clone)]
+pub struct JiraConfig {
+    pub host: String,
+    pub user: String,
+    pub pass: String
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FileConfig {
+    default_profile: Option<String>,
+
+    #[serde(default)]
+    profiles: HashMap<String, Profile>
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+struct Profile {
+    root: Option<String>,
+    include_archive: Option<bool>,
+    day_start: Option<u32>,
+
+    #[serde(default)]
+    jira: JiraProfile
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+struct JiraProfile {
+    host: Option<String>,
+    user: Option<String>,
+    pass: Option<String>
+}
+
+/// Selects the profile used by every following configuration lookup.
+/// Without one, `default_profile` from the config file is used (if any).
+/// Only the first call has effect.
+pub fn select_profile(name: Option<String>) {
+    PROFILE.set(name).ok();
+}
+
+/// Config file location: `XFEL_WORKLOG_CONFIG` if set, otherwise
+/// `$XDG_CONFIG_HOME/xfel-worklog/config.toml` (`~/.config` when unset).
+pub fn path() -> Option<PathBuf> {
+    if let Ok(p) = env::var("XFEL_WORKLOG_CONFIG") {
+        return Some(PathBuf::from(p));
+    }
+    let base = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|x| PathBuf::from(x).join(".config")))?;
+    Some(base.join("xfel-worklog").join("config.toml"))
+}
+
+/// Directory for cached data: `$XDG_CACHE_HOME/xfel-worklog`
+/// (`~/.cache` when unset).
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var("XDG_CACHE_HOME")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|x| PathBuf::from(x).join(".cache")))?;
+    Some(base.join("xfel-worklog"))
+}
+
+/// File `{kind}{root}.{ext}` under [`cache_dir`], with `root` flattened so
+/// every diary root gets its own.
+pub fn cache_file(root: &str, kind: &str, ext: &str) -> Option<PathBuf> {
+    let name: String = root
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    Some(cache_dir()?.join(format!("{}{}.{}", kind, name, ext)))
+}
+
+fn read_file() -> Result<FileConfig> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(FileConfig::default())
+    };
+    let path_str = path.to_string_lossy().to_string();
+    match fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .map_err(|e| Error::Config(format!("{}: {}", path_str, e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(FileConfig::default()),
+        Err(e) => Err(Error::Io(path_str, e))
+    }
+}
+
+fn profile() -> Result<(String, Profile)> {
+    let mut file = read_file()?;
+    let selected = PROFILE.get().cloned().flatten();
+    let name = match selected.or(file.default_profile) {
+        Some(name) => name,
+        None => return Ok((String::from("default"), Profile::default()))
+    };
+    match file.profiles.remove(&name) {
+        Some(profile) => Ok((name, profile)),
+        None => Err(Error::Config(format!("Unknown profile: {}", name)))
+    }
+}
+
+fn missing(var: &str, key: &str, profile: &str) -> Error {
+    Error::Config(
+        format!("set {} or `{}` in profile `{}` of config file", var, key, profile)
+    )
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+/// `hour` when it is a valid `day_start`, from 0 to 23.
+pub fn check_day_start(hour: u32) -> Result<u32> {
+    if hour > 23 {
+        let msg = format!("day_start must be an hour from 0 to 23, not {}", hour);
+        return Err(Error::Config(msg));
+    }
+    Ok(hour)
+}
+
+/// Diary config from selected profile, `DIARY_*` env variables override it.
+pub fn diary() -> Result<DiaryConfig> {
+    let (name, profile) = profile()?;
+    let include_archive = match env_var("DIARY_INCLUDE_ARCHIVE") {
+        Some(x) => Some(x.parse::<bool>().map_err(|e| {
+            Error::Config(format!("DIARY_INCLUDE_ARCHIVE: {}", e))
+        })?),
+        None => profile.include_archive
+    };
+    let day_start = match env_var("DIARY_DAY_START") {
+        Some(x) => Some(x.parse::<u32>().map_err(|e| {
+            Error::Config(format!("DIARY_DAY_START: {}", e))
+        })?),
+        None => profile.day_start
+    };
+    let day_start = day_start.map(check_day_start).transpose()?;
+    Ok(DiaryConfig {
+        root: env_var("DIARY_ROOT")
+            .or(profile.root)
+            .ok_or_else(|| missing("DIARY_ROOT", "root", &name))?,
+        include_archive,
+        day_start
+    })
+}
+
+/// Jira config from selected profile, `JIRA_*` env variables override it.
+pub fn jira() -> Result<JiraConfig> {
+    let (name, profile) = profile()?;
+    let jira = profile.jira;
+    Ok(JiraConfig {
+        host: env_var("JIRA_HOST")
+            .or(jira.host)
+            .ok_or_else(|| missing("JIRA_HOST", "jira.host", &name))?,
+        user: env_var("JIRA_USER")
+            .or(jira.user)
+            .ok_or_else(|| missing("JIRA_USER", "jira.user", &name))?,
+        pass: env_var("JIRA_PASS")
+            .or(jira.pass)
+            .ok_or_else(|| missing("JIRA_PASS", "jira.pass", &name))?
+    })
+}
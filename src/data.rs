@@ -1,82 +1,269 @@
+pub mod doctor;
+pub mod field_filter;
+mod frontmatter;
+pub mod index;
 pub mod model;
 pub mod query;
+pub mod search;
+pub mod tag_expr;
+pub mod worklog;
 
-use std::{fs::OpenOptions, process, path::Path};
-use log::{warn, info, error};
+use std::{collections::HashSet, fs::OpenOptions, io, path::Path, process};
+use log::{warn, info};
+use rayon::prelude::*;
 use model::{DiaryDoc,Metadata};
-use serde::{Serialize, Deserialize};
 use std::fs::File;
 use std::fs;
 use std::io::prelude::*;
-use super::jira::JiraTicket;
+use crate::config::{self, DiaryConfig};
+use crate::error::{Error, Result};
+use crate::jira::JiraTicket;
+use index::{Index, Stamp};
+use serde_yaml::Value;
 use walkdir::{WalkDir, DirEntry};
+use worklog::WorklogEntry;
 use yaml_front_matter::YamlFrontMatter;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Config {
-    root: String,
-    include_archive: Option<bool>
+fn conf() -> Result<DiaryConfig> {
+    config::diary()
 }
 
-fn conf() -> Config {
-    envy::prefixed("DIARY_").from_env().unwrap()
+// Config, making sure its root is there
+fn checked_conf() -> Result<DiaryConfig> {
+    let conf = conf()?;
+    if !Path::new(&conf.root).is_dir() {
+        return Err(
+            Error::Config(format!("Diary root is not a directory: {}", conf.root))
+        );
+    }
+    Ok(conf)
 }
 
 fn is_archive(x: &DirEntry) -> bool {
-    x.path().to_str().unwrap().contains("_archive")
+    x.path().to_string_lossy().contains("_archive")
 }
 
-pub fn load_diary() -> Vec<DiaryDoc> {
-    let mut output: Vec<DiaryDoc> = Vec::new();
-    let mut iter: Vec<DirEntry> = WalkDir::new(conf().root) 
+fn io_error(path: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |e| Error::Io(path.to_string(), e)
+}
+
+// Tells apart an invalid worklog entry from any other frontmatter problem,
+// so the former can be reported with the offending entry.
+fn parse_error(path: &str, md: &str, e: Box<dyn std::error::Error>) -> Error {
+    if let Ok(doc) = YamlFrontMatter::parse::<serde_yaml::Value>(md) {
+        let worklog = doc.metadata
+            .get("worklog")
+            .and_then(|x| x.as_sequence())
+            .cloned()
+            .unwrap_or_default();
+        for entry in worklog.iter().filter_map(|x| x.as_str()) {
+            if let Err(e) = entry.parse::<WorklogEntry>() {
+                return Error::Worklog(path.to_string(), e);
+            }
+        }
+    }
+    Error::Frontmatter(path.to_string(), e.to_string())
+}
+
+fn parse_metadata(path: &str, md: &str) -> Result<Metadata> {
+    YamlFrontMatter::parse::<Metadata>(md)
+        .map(|doc| doc.metadata)
+        .map_err(|e| parse_error(path, md, e))
+}
+
+// Metadata of a file for reading only: worklog entries that can't be
+// parsed are left out, so the rest of the doc still counts, and the first
+// of them is returned along.
+fn read_metadata(path: &str, md: &str) -> Result<(Metadata, Option<Error>)> {
+    let e = match parse_metadata(path, md) {
+        Ok(x) => return Ok((x, None)),
+        Err(e @ Error::Worklog(_, _)) => e,
+        Err(e) => return Err(e)
+    };
+    let frontmatter_error = |e: String| Error::Frontmatter(path.to_string(), e);
+    let mut raw = YamlFrontMatter::parse::<Value>(md)
+        .map_err(|e| frontmatter_error(e.to_string()))?
+        .metadata;
+    if let Some(items) = raw.get_mut("worklog").and_then(Value::as_sequence_mut) {
+        items.retain(|x| x.as_str().is_none_or(|x| x.parse::<WorklogEntry>().is_ok()));
+    }
+    let metadata = serde_yaml::from_value(raw).map_err(|e| frontmatter_error(e.to_string()))?;
+    Ok((metadata, Some(e)))
+}
+
+// Every markdown file under `root`, in path order. Top level directories
+// are walked in parallel.
+fn diary_files(root: &str, include_archive: bool) -> Vec<DirEntry> {
+    let walk = |path: &Path| -> Vec<DirEntry> {
+        WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .collect()
+    };
+    let top: Vec<DirEntry> = WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(1)
+        .sort_by_file_name()
         .into_iter()
-        .filter_map(Result::ok)
+        .filter_map(|e| e.ok())
+        .collect();
+    let mut files: Vec<DirEntry> = top
+        .into_par_iter()
+        .flat_map_iter(|e| if e.file_type().is_dir() { walk(e.path()) } else { vec!(e) })
         .filter(|e| !e.file_type().is_dir())
-        .filter(|e| !e.path().to_str().unwrap().contains(".git"))
+        .filter(|e| !e.path().to_string_lossy().contains(".git"))
+        .filter(|e| e.path().to_string_lossy().ends_with("md"))
         .collect();
 
-    let include_archive = conf().include_archive.unwrap_or(false);
     if !include_archive {
-        iter = iter
-            .into_iter()
-            .filter(|e| !is_archive(e))
-            .collect();
-    }
-    
-    for entry in iter {
-        let path = String::from(entry.path().to_str().unwrap());
-        if path.ends_with("md") {
-            let md = fs::read_to_string(&path).unwrap();
-            match YamlFrontMatter::parse(&md) {
-                Ok(doc) => {
-                    output.push(DiaryDoc {
-                        metadata: doc.metadata,
-                        path
-                    });
-                }
-                Err(e) => {
-                    warn!("Error with path {}", path);
-                    warn!("{}", e);
-                    output.push(DiaryDoc {
-                        metadata: Metadata {
-                            author: None,
-                            date: None, 
-                            tags: vec!(), 
-                            estimate: None, 
-                            worklog: vec!() 
-                        },
-                        path
-                    });
-                }
-            }
+        files.retain(|e| !is_archive(e));
+    }
+    files
+}
+
+// A file as read by `load_diary`
+struct Loaded {
+    doc: DiaryDoc,
+
+    // Set when it had to be parsed and can go into the index
+    stamp: Option<Stamp>,
+
+    // Set when its frontmatter is invalid
+    error: Option<Error>
+}
+
+fn load_file(entry: &DirEntry, index: &Index) -> Result<Loaded> {
+    let path = entry.path().to_string_lossy().to_string();
+    let stamp = Stamp::of(entry);
+    let cached = stamp.as_ref().and_then(|x| index.get(&path, x));
+    if let Some(metadata) = cached {
+        let doc = DiaryDoc { metadata, path };
+        return Ok(Loaded { doc, stamp: None, error: None });
+    }
+    let md = fs::read_to_string(&path).map_err(io_error(&path))?;
+    match read_metadata(&path, &md) {
+        Ok((metadata, None)) => {
+            let doc = DiaryDoc { metadata, path };
+            Ok(Loaded { doc, stamp, error: None })
+        }
+        Ok((metadata, skipped)) => {
+            let doc = DiaryDoc { metadata, path };
+            Ok(Loaded { doc, stamp: None, error: skipped })
+        }
+        Err(e) => {
+            let doc = DiaryDoc { metadata: Metadata::default(), path };
+            Ok(Loaded { doc, stamp: None, error: Some(e) })
+        }
+    }
+}
+
+/// Walks diary root and parses every markdown file found there.
+/// Files under `_archive` are skipped unless `include_archive` is set.
+/// See [`config::diary`].
+///
+/// Files with an invalid frontmatter are returned with empty metadata,
+/// the problem is logged as a warning. Worklog entries that can't be
+/// parsed are left out of their doc and logged the same way.
+///
+/// Parsed frontmatters are kept in an [`Index`], only files changed since
+/// last call are read again. Walking and parsing run on a thread pool,
+/// docs are returned sorted by path.
+pub fn load_diary() -> Result<Vec<DiaryDoc>> {
+    load_diary_with_archive(conf()?.include_archive.unwrap_or(false))
+}
+
+/// Same as [`load_diary`] but choosing whether `_archive` is included
+/// instead of taking it from config.
+pub fn load_diary_with_archive(include_archive: bool) -> Result<Vec<DiaryDoc>> {
+    let conf = checked_conf()?;
+    let files = diary_files(&conf.root, include_archive);
+    let mut index = Index::load(&conf.root);
+    let loaded: Vec<Result<Loaded>> = files
+        .par_iter()
+        .map(|x| load_file(x, &index))
+        .collect();
+
+    let mut output: Vec<DiaryDoc> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for item in loaded {
+        let Loaded { doc, stamp, error } = item?;
+        seen.insert(doc.path.to_string());
+        if let Some(x) = stamp {
+            index.insert(&doc.path, x, &doc.metadata);
+        }
+        if let Some(e) = error {
+            warn!("{}", e);
         }
+        output.push(doc);
+    }
+    index.retain(|x| seen.contains(x) || (!include_archive && x.contains("_archive")));
+    index.save(&conf.root);
+    Ok(output)
+}
+
+/// Drops the index and parses the whole diary again, returns how many
+/// documents were found.
+pub fn reindex() -> Result<usize> {
+    index::remove(&conf()?.root)?;
+    Ok(load_diary()?.len())
+}
+
+/// Advisory lock over the whole diary, released on drop.
+///
+/// Anything doing read-modify-write of diary files (start/stop, archive)
+/// should hold it from the moment docs are loaded until they are written
+/// back, so concurrent invocations run one after the other. The lock file
+/// lives next to the index (see [`config::cache_dir`]), out of the diary.
+pub struct DiaryLock {
+    _file: File
+}
+
+/// Blocks until the diary lock is acquired.
+pub fn lock_diary() -> Result<DiaryLock> {
+    let path = config::cache_file(&conf()?.root, "lock", "lock").ok_or_else(|| Error::Config(
+        String::from("No cache directory for the diary lock, set XDG_CACHE_HOME or HOME")
+    ))?;
+    let path_str = path.to_string_lossy().to_string();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error(&path_str))?;
     }
-    output
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(io_error(&path_str))?;
+    file.lock().map_err(io_error(&path_str))?;
+    Ok(DiaryLock { _file: file })
+}
+
+// Writes into a temp file next to `path` and renames it over `path`, so
+// readers see either the old or the new content, never a partial one.
+fn write_atomic(path: &str, contents: &str) -> Result<()> {
+    let target = Path::new(path);
+    let fname = target.file_name().unwrap().to_string_lossy();
+    let tmp = target.with_file_name(format!(".{}.{}.tmp", fname, process::id()));
+    let tmp_str = tmp.to_string_lossy().to_string();
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        if let Ok(meta) = fs::metadata(target) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, target)
+    })();
+    if let Err(e) = result {
+        fs::remove_file(&tmp).ok();
+        return Err(Error::Io(tmp_str, e));
+    }
+    Ok(())
 }
 
 fn replace_frontmatter(content: &str, new_fm: &str) -> String {
-    if content.starts_with("---") {
-        if let Some(end) = content[3..].find("---") {
+    if let Some(rest) = content.strip_prefix("---") {
+        if let Some(end) = rest.find("---") {
             let fm_end = end + 6; // 3 for opening and 3 for closure
             return format!("---\n{}\n---{}", new_fm, &content[fm_end..]);
         } else {
@@ -85,63 +272,138 @@ fn replace_frontmatter(content: &str, new_fm: &str) -> String {
     } else {
         info!("No frontmatter detected");
     }
-    return format!("---\n{}\n---{}", new_fm, content)
+    format!("---\n{}\n---{}", new_fm, content)
 }
 
-pub fn archive_entry(doc: DiaryDoc) {
-    let root = conf().root;
+/// Moves `doc` into `_archive` directory under diary root, keeping its relative path.
+pub fn archive_entry(doc: DiaryDoc) -> Result<()> {
+    let root = conf()?.root;
     let last_part = String::from(&doc.path).split_off(root.len() + 1);
     let target = Path::new(&root).join("_archive").join(last_part);
-    let target_str = target.to_str().unwrap();
-    let target_dir = &target.parent().unwrap();
-    info!("Target dir: {}", target_dir.to_str().unwrap());
-    match fs::create_dir_all(target_dir) {
-        Ok(_) => {
-            fs::rename(&doc.path, target_str).unwrap();
-            info!("Archived: {} -> {}", &doc.path, target_str);
+    let target_str = target.to_string_lossy().to_string();
+    let target_dir = target.parent().unwrap();
+    info!("Target dir: {}", target_dir.to_string_lossy());
+    fs::create_dir_all(target_dir)
+        .map_err(io_error(&target_dir.to_string_lossy()))?;
+    fs::rename(&doc.path, &target_str).map_err(io_error(&doc.path))?;
+    info!("Archived: {} -> {}", &doc.path, target_str);
+    Ok(())
+}
+
+// Only worklog changes are patched in place, anything else (or a patch
+// that wouldn't read back as `doc`'s metadata) goes through a full rewrite.
+fn patch_worklog(
+    doc: &DiaryDoc, contents: &str, current: Metadata, normalize: bool
+) -> Option<String> {
+    let only_worklog = Metadata {
+        worklog: doc.metadata.worklog.clone(),
+        ..current
+    };
+    if only_worklog != doc.metadata {
+        return None;
+    }
+    let patched = frontmatter::patch_worklog(contents, &doc.metadata.worklog, normalize)?;
+    match read_metadata(&doc.path, &patched) {
+        Ok((x, _)) if x == doc.metadata => Some(patched),
+        _ => None
+    }
+}
+
+// Current frontmatter of a file, leaving its worklog out when normalizing
+// so it doesn't need to be valid. Otherwise entries that can't be parsed
+// are left out, and the first of them returned along.
+fn current_metadata(
+    path: &str, contents: &str, normalize: bool
+) -> Result<(Metadata, Option<Error>)> {
+    if !normalize {
+        return read_metadata(path, contents);
+    }
+    let frontmatter_error = |e: String| Error::Frontmatter(path.to_string(), e);
+    let mut raw = YamlFrontMatter::parse::<Value>(contents)
+        .map_err(|e| frontmatter_error(e.to_string()))?
+        .metadata;
+    if let Some(x) = raw.as_mapping_mut() {
+        if x.contains_key("worklog") {
+            x.insert(Value::from("worklog"), Value::Sequence(vec!()));
         }
-        Err(e) => error!("{}", e)
     }
+    let metadata = serde_yaml::from_value(raw).map_err(|e| frontmatter_error(e.to_string()))?;
+    Ok((metadata, None))
 }
 
-pub fn update_entry(doc: DiaryDoc) {
-    info!("Updating: {}", doc.path);
-    let mut file_r = File::open(&doc.path).unwrap();
+/// Current contents of `doc`'s file and the ones [`update_entry`] (or
+/// [`normalize_entry`] when `normalize` is set) would write.
+pub fn render_entry(doc: &DiaryDoc, normalize: bool) -> Result<(String, String)> {
+    let mut file_r = File::open(&doc.path).map_err(io_error(&doc.path))?;
     let mut contents = String::new();
-    file_r.read_to_string(&mut contents).unwrap();
-    let yaml = serde_yaml::to_string(&doc.metadata).unwrap();
+    file_r.read_to_string(&mut contents).map_err(io_error(&doc.path))?;
+    if contents.starts_with("---") {
+        let (current, skipped) = current_metadata(&doc.path, &contents, normalize)?;
+        if let Some(patched) = patch_worklog(doc, &contents, current, normalize) {
+            return Ok((contents, patched));
+        }
+        // A full rewrite would lose entries that can't be parsed
+        if let Some(e) = skipped {
+            return Err(e);
+        }
+        info!("Rewriting whole frontmatter of {}", doc.path);
+    }
+    let yaml = serde_yaml::to_string(&doc.metadata)
+        .map_err(|e| Error::Frontmatter(doc.path.to_string(), e.to_string()))?;
     let new_contents = replace_frontmatter(&contents, &yaml);
+    Ok((contents, new_contents))
+}
 
-    let mut file_w = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&doc.path)
-        .unwrap();
-    file_w.write_all(new_contents.as_bytes()).unwrap();
+/// Writes `doc`'s metadata back into its file's frontmatter.
+///
+/// When only the worklog changed, just its lines are edited so comments,
+/// quoting and key order elsewhere are kept as they are. Otherwise the whole
+/// frontmatter is reserialized.
+///
+/// Worklog lines that can't be parsed are kept as they are. It fails
+/// without touching the file when its current frontmatter can't be parsed,
+/// or has such lines and needs a full rewrite, as that would lose whatever
+/// is there. The file is
+/// replaced atomically, concurrent writers should hold [`lock_diary`].
+pub fn update_entry(doc: DiaryDoc) -> Result<()> {
+    info!("Updating: {}", doc.path);
+    let (_, new_contents) = render_entry(&doc, false)?;
+    write_atomic(&doc.path, &new_contents)
 }
 
-pub fn create_entry(ticket: JiraTicket, base_path: Option<&str>) {
-    let key_parts: Vec<&str> = ticket.key.split("-").collect();
+/// Same as [`update_entry`] but worklog lines not in canonical form are
+/// written again, even when they hold the same entry. Lines already in it
+/// are kept with their comments. File's current worklog entries don't need
+/// to be valid, the rest of its frontmatter does.
+pub fn normalize_entry(doc: DiaryDoc) -> Result<()> {
+    info!("Normalizing: {}", doc.path);
+    let (_, new_contents) = render_entry(&doc, true)?;
+    write_atomic(&doc.path, &new_contents)
+}
+
+/// Creates a new diary file for a fetched Jira ticket.
+pub fn create_entry(ticket: JiraTicket, base_path: Option<&str>) -> Result<()> {
+    let key_parts: Vec<&str> = ticket.key.split('-').collect();
     let mut tags = vec!(key_parts[0].to_string(), ticket.key.to_string());
     let mut dir = key_parts[0].to_string();
     if let Some(base) = base_path {
         tags.push(base.to_string());
         dir = format!("{}/{}", base, key_parts[0]);
     } 
-    let path = format!("{}/{}/{}.md", conf().root, dir, ticket.key);
+    let path = format!("{}/{}/{}.md", conf()?.root, dir, ticket.key);
     info!("Writing entry into {}", path);
-    if File::open(&path).is_ok() {
-        error!("{} already exists.", &path);
-        process::exit(1);
+    if Path::new(&path).exists() {
+        let e = io::Error::new(io::ErrorKind::AlreadyExists, "already exists");
+        return Err(Error::Io(path, e));
     }
     let metadata = Metadata {
         author: Some(ticket.fields.creator.display_name),
         date: None,
         tags,
         estimate: ticket.fields.timetracking.original_estimate,
-        worklog: vec!()
+        ..Default::default()
     };
-    let mut file = File::create(path).unwrap();
+    let mut file = File::create(&path).map_err(io_error(&path))?;
     let comments: String = ticket.fields.comment.comments
         .iter()
         .map(|x| format!("## {}\n{}", x.author.display_name, x.body))
@@ -149,11 +411,49 @@ pub fn create_entry(ticket: JiraTicket, base_path: Option<&str>) {
         .join("\n\n");
     let file_data = format!(
         "---\n{}---\n# {}\n\n{}\n\n# Comments\n\n{}",
-        serde_yaml::to_string(&metadata).unwrap(),
+        serde_yaml::to_string(&metadata)
+            .map_err(|e| Error::Frontmatter(path.to_string(), e.to_string()))?,
         ticket.fields.summary, 
-        ticket.fields.description.unwrap_or("".to_string()),
+        ticket.fields.description.unwrap_or_default(),
         comments
     );
-    file.write_all(file_data.as_bytes()).unwrap();
+    file.write_all(file_data.as_bytes()).map_err(io_error(&path))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const BAD: &str = "- 2022-10-11T10:00:00+02:00,2022-10-11T1100:00+02:00  # typo\n";
+
+    // Same as `action A-2 start`: stop every active doc, then start A-2
+    #[test]
+    fn start_with_unparseable_entry_in_active_doc() {
+        let root = env::temp_dir().join(format!("xfel-worklog-test-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let a1 = root.join("A-1.md");
+        let a2 = root.join("A-2.md");
+        let open = "- 2022-10-12T09:00:00+02:00,\n";
+        fs::write(&a1, format!("---\ntags: []\nworklog:\n{}{}---\nbody\n", BAD, open)).unwrap();
+        fs::write(&a2, "---\ntags: []\nworklog: []\n---\n").unwrap();
+        env::set_var("DIARY_ROOT", &root);
+        env::set_var("XDG_CACHE_HOME", root.join("cache"));
+
+        let active = query::active().unwrap();
+        assert_eq!(active.len(), 1);
+        for mut doc in active {
+            doc.stop();
+            update_entry(doc).unwrap();
+        }
+        let mut doc = query::by_path("A-2").unwrap().pop().unwrap();
+        doc.start();
+        update_entry(doc).unwrap();
+
+        let a1_contents = fs::read_to_string(&a1).unwrap();
+        assert!(a1_contents.starts_with(&format!("---\ntags: []\nworklog:\n{}", BAD)));
+        assert!(!a1_contents.contains(open));
+        assert_eq!(query::active().unwrap()[0].path, a2.to_string_lossy());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use chrono::{DateTime, Duration, FixedOffset};
+use rayon::prelude::*;
+use serde_yaml::Value;
+use yaml_front_matter::YamlFrontMatter;
+use super::model::{now, DiaryDoc, Metadata};
+use super::worklog::WorklogEntry;
+use super::{checked_conf, diary_files, io_error};
+use crate::error::Result;
+
+/// Something wrong found on a diary file.
+#[derive(Debug, Clone)]
+pub enum Problem {
+    /// Frontmatter is missing or isn't valid YAML for a diary doc.
+    Frontmatter(String),
+
+    /// A worklog item that can't be read as an entry, with the reason.
+    Unparseable(String, String),
+
+    EndBeforeStart(WorklogEntry),
+
+    /// An entry overlapping an earlier one, of the same doc when path is
+    /// `None`.
+    Overlap(WorklogEntry, Option<String>, WorklogEntry),
+
+    /// An open entry while a later one was opened, of the same doc when
+    /// path is `None`.
+    MultipleOpen(WorklogEntry, Option<String>, WorklogEntry)
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Frontmatter(e) => write!(f, "invalid frontmatter: {}", e),
+            Problem::Unparseable(raw, e) => {
+                write!(f, "unparseable worklog entry '{}': {}", raw, e)
+            }
+            Problem::EndBeforeStart(x) => write!(f, "entry ends before it starts: {}", x),
+            Problem::Overlap(x, None, other) => {
+                write!(f, "entry {} overlaps {}", x, other)
+            }
+            Problem::Overlap(x, Some(path), other) => {
+                write!(f, "entry {} overlaps {} of {}", x, other, path)
+            }
+            Problem::MultipleOpen(x, None, later) => {
+                write!(f, "entry {} is still open, {} was opened later", x, later)
+            }
+            Problem::MultipleOpen(x, Some(path), later) => {
+                write!(f, "entry {} is still open, {} of {} was opened later", x, later, path)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub path: String,
+    pub problem: Problem
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+// A worklog item as found in frontmatter
+struct Item {
+    // Its string value, or its YAML when it isn't a string
+    raw: String,
+    entry: Option<WorklogEntry>
+}
+
+// A file as seen by the doctor, with every entry that could be parsed
+struct Scanned {
+    path: String,
+
+    // Frontmatter with an empty worklog, `None` when even that is invalid
+    metadata: Option<Metadata>,
+    items: Vec<Item>,
+    entries: Vec<WorklogEntry>,
+    problems: Vec<Problem>
+}
+
+// Reads the worklog from the raw YAML, so a bad entry doesn't hide the
+// rest of them as it does when parsing into `Metadata`.
+fn scan(path: String, md: &str) -> Scanned {
+    let mut scanned = Scanned {
+        path,
+        metadata: None,
+        items: vec!(),
+        entries: vec!(),
+        problems: vec!()
+    };
+    let mut raw = match YamlFrontMatter::parse::<Value>(md) {
+        Ok(x) => x.metadata,
+        Err(e) => {
+            scanned.problems.push(Problem::Frontmatter(e.to_string()));
+            return scanned;
+        }
+    };
+    let worklog = raw.as_mapping_mut().and_then(|x| {
+        let worklog = x.remove("worklog");
+        if worklog.is_some() {
+            x.insert(Value::from("worklog"), Value::Sequence(vec!()));
+        }
+        worklog
+    });
+    match worklog {
+        None | Some(Value::Null) => (),
+        Some(Value::Sequence(items)) => {
+            for item in items {
+                let (raw, parsed) = match item.as_str() {
+                    Some(x) => (
+                        x.to_string(),
+                        x.parse::<WorklogEntry>().map_err(|e| e.to_string())
+                    ),
+                    None => {
+                        let text = serde_yaml::to_string(&item).unwrap_or_default();
+                        (text.trim().to_string(), Err(String::from("not a string")))
+                    }
+                };
+                if let Err(e) = &parsed {
+                    scanned.problems.push(Problem::Unparseable(raw.clone(), e.clone()));
+                }
+                scanned.items.push(Item { raw, entry: parsed.ok() });
+            }
+        }
+        Some(_) => {
+            let msg = String::from("worklog is not a list");
+            scanned.problems.push(Problem::Frontmatter(msg));
+        }
+    }
+    match serde_yaml::from_value::<Metadata>(raw) {
+        Ok(x) => scanned.metadata = Some(x),
+        Err(e) => scanned.problems.push(Problem::Frontmatter(e.to_string()))
+    }
+    scanned.entries = scanned.items.iter().filter_map(|x| x.entry.clone()).collect();
+
+    for x in scanned.entries.iter() {
+        if x.end.map(|end| end < x.start).unwrap_or(false) {
+            scanned.problems.push(Problem::EndBeforeStart(x.clone()));
+        }
+    }
+    scanned
+}
+
+// Every open entry but the latest of the whole diary, which is what
+// `close_stale` closes besides stale ones.
+fn multiple_open(scanned: &[Scanned]) -> Vec<Diagnostic> {
+    let open: Vec<(&str, &WorklogEntry)> = scanned
+        .iter()
+        .flat_map(|x| x.entries.iter().map(move |e| (x.path.as_str(), e)))
+        .filter(|(_, e)| !e.is_complete())
+        .collect();
+    let (last_path, last) = match open.iter().max_by_key(|(_, e)| e.start) {
+        Some(x) => *x,
+        None => return vec!()
+    };
+    open.iter()
+        .filter(|(_, e)| e.start < last.start)
+        .map(|(path, entry)| Diagnostic {
+            path: path.to_string(),
+            problem: Problem::MultipleOpen(
+                (*entry).clone(),
+                Some(last_path.to_string()).filter(|x| x != path),
+                last.clone()
+            )
+        })
+        .collect()
+}
+
+// Sweeps complete entries of every doc by start, each one is reported as
+// overlapping those still running when it starts.
+fn overlaps(scanned: &[Scanned]) -> Vec<Diagnostic> {
+    let mut all: Vec<(&str, &WorklogEntry)> = scanned
+        .iter()
+        .flat_map(|x| x.entries.iter().map(move |e| (x.path.as_str(), e)))
+        .filter(|(_, e)| e.end.map(|end| end > e.start).unwrap_or(false))
+        .collect();
+    all.sort_by_key(|(_, e)| e.start);
+
+    let mut output = vec!();
+    let mut running: Vec<(&str, &WorklogEntry)> = vec!();
+    for (path, entry) in all {
+        running.retain(|(_, x)| x.end.unwrap() > entry.start);
+        for (other_path, other) in running.iter() {
+            let other_path = Some(other_path.to_string()).filter(|x| x != path);
+            output.push(Diagnostic {
+                path: path.to_string(),
+                problem: Problem::Overlap(entry.clone(), other_path, (*other).clone())
+            });
+        }
+        running.push((path, entry));
+    }
+    output
+}
+
+fn scan_diary(include_archive: bool) -> Result<Vec<Scanned>> {
+    let conf = checked_conf()?;
+    let files = diary_files(&conf.root, include_archive);
+    files
+        .par_iter()
+        .map(|x| {
+            let path = x.path().to_string_lossy().to_string();
+            let md = fs::read_to_string(&path).map_err(io_error(&path))?;
+            Ok(scan(path, &md))
+        })
+        .collect()
+}
+
+/// Checks every file of the diary (under `_archive` too when
+/// `include_archive` is set), returns problems sorted by path.
+pub fn diagnose(include_archive: bool) -> Result<Vec<Diagnostic>> {
+    let scanned = scan_diary(include_archive)?;
+
+    let mut output: Vec<Diagnostic> = scanned
+        .iter()
+        .flat_map(|x| x.problems.iter().map(|p| Diagnostic {
+            path: x.path.clone(),
+            problem: p.clone()
+        }))
+        .collect();
+    output.extend(overlaps(&scanned));
+    output.extend(multiple_open(&scanned));
+    output.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(output)
+}
+
+// Second chance for a timestamp: `2022-10-11 15:44:00`, `2022-10-11T15:44`
+fn repair_ts(s: &str) -> String {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let mut ts = match parts.as_slice() {
+        [date, time] => format!("{}T{}", date, time),
+        _ => parts.concat()
+    };
+    if ts.len() == "2022-10-11T15:44".len() {
+        ts.push_str(":00");
+    }
+    ts
+}
+
+fn repair(raw: &str) -> Option<WorklogEntry> {
+    let parts: Vec<&str> = raw.splitn(3, ',').collect();
+    let mut fixed: Vec<String> = parts.iter().take(2).map(|x| repair_ts(x)).collect();
+    fixed.extend(parts.get(2).map(|x| x.to_string()));
+    fixed.join(",").parse().ok()
+}
+
+// Open entries older than `close_after`, or followed by a later open one,
+// are closed `close_after` after they started, or when the next entry of
+// the whole diary starts if that comes first.
+fn close_stale(docs: &mut [Vec<WorklogEntry>], close_after: Duration) {
+    let now = now();
+    let mut starts: Vec<DateTime<FixedOffset>> = docs
+        .iter()
+        .flat_map(|x| x.iter().map(|e| e.start))
+        .collect();
+    starts.sort();
+    let last_open = docs
+        .iter()
+        .flat_map(|x| x.iter().filter(|e| !e.is_complete()).map(|e| e.start))
+        .max();
+    for entry in docs.iter_mut().flat_map(|x| x.iter_mut()) {
+        if entry.is_complete() {
+            continue;
+        }
+        if now - entry.start <= close_after && Some(entry.start) == last_open {
+            continue;
+        }
+        let next = starts.iter().find(|x| **x > entry.start);
+        let mut end = (entry.start + close_after).min(now);
+        if let Some(x) = next {
+            end = end.min(*x);
+        }
+        entry.end = Some(end.with_timezone(entry.start.offset()));
+    }
+}
+
+// Where entries overlap, time belongs to the one that started last (the
+// one later in path order on a tie). Earlier ones are cut around it, so
+// they can end up split in two or gone.
+fn split_overlaps(docs: &mut [Vec<WorklogEntry>]) {
+    let mut all: Vec<(usize, usize)> = docs
+        .iter()
+        .enumerate()
+        .flat_map(|(d, x)| x.iter().enumerate().map(move |(e, _)| (d, e)))
+        .filter(|(d, e)| {
+            let entry = &docs[*d][*e];
+            entry.end.map(|end| end > entry.start).unwrap_or(false)
+        })
+        .collect();
+    all.sort_by_key(|(d, e)| (docs[*d][*e].start, *d, *e));
+
+    // Clusters of entries overlapping each other, directly or not
+    let mut clusters: Vec<Vec<(usize, usize)>> = vec!();
+    let mut cluster_end: Option<DateTime<FixedOffset>> = None;
+    for (d, e) in all {
+        let entry = &docs[d][e];
+        match cluster_end {
+            Some(x) if entry.start < x => clusters.last_mut().unwrap().push((d, e)),
+            _ => clusters.push(vec!((d, e)))
+        }
+        cluster_end = cluster_end.max(entry.end);
+    }
+
+    let mut pieces: HashMap<(usize, usize), Vec<WorklogEntry>> = HashMap::new();
+    for cluster in clusters.iter().filter(|x| x.len() > 1) {
+        let mut bounds: Vec<DateTime<FixedOffset>> = cluster
+            .iter()
+            .flat_map(|(d, e)| [docs[*d][*e].start, docs[*d][*e].end.unwrap()])
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+        for key in cluster {
+            pieces.insert(*key, vec!());
+        }
+        for span in bounds.windows(2) {
+            let owner = cluster.iter().rev().find(|(d, e)| {
+                let entry = &docs[*d][*e];
+                entry.start <= span[0] && entry.end.unwrap() >= span[1]
+            });
+            let (d, e) = match owner {
+                Some(x) => *x,
+                None => continue
+            };
+            let entry = &docs[d][e];
+            let owned = pieces.get_mut(&(d, e)).unwrap();
+            match owned.last_mut() {
+                Some(last) if last.end == Some(span[0]) => last.end = Some(span[1]),
+                _ => owned.push(WorklogEntry {
+                    start: span[0],
+                    end: Some(span[1]),
+                    note: entry.note.clone()
+                })
+            }
+        }
+    }
+
+    for (d, doc) in docs.iter_mut().enumerate() {
+        let entries = std::mem::take(doc);
+        for (e, entry) in entries.into_iter().enumerate() {
+            match pieces.remove(&(d, e)) {
+                Some(split) => doc.extend(split.into_iter().map(|x| WorklogEntry {
+                    start: x.start.with_timezone(entry.start.offset()),
+                    end: x.end.map(|end| end.with_timezone(entry.start.offset())),
+                    note: x.note
+                })),
+                None => doc.push(entry)
+            }
+        }
+    }
+}
+
+/// Docs of the diary whose worklog [`fix`] would change, with it already
+/// changed. Their files should be written with
+/// [`normalize_entry`](super::normalize_entry).
+///
+/// Entries are written in canonical form, fixing whitespace and timestamps
+/// when possible. Stale open entries are closed `close_after` after they
+/// started (see `close_stale`), zero-length ones are dropped and
+/// overlapping ones are split so no time counts twice.
+///
+/// Files with an invalid frontmatter, or an entry that can't be read even
+/// after fixing its format, are left out.
+pub fn fix(include_archive: bool, close_after: Duration) -> Result<Vec<DiaryDoc>> {
+    let mut scanned = scan_diary(include_archive)?;
+    for x in scanned.iter_mut() {
+        for item in x.items.iter_mut().filter(|i| i.entry.is_none()) {
+            item.entry = repair(&item.raw);
+        }
+    }
+    scanned.retain(|x| {
+        x.metadata.is_some() && x.items.iter().all(|i| i.entry.is_some())
+    });
+    let mut worklogs: Vec<Vec<WorklogEntry>> = scanned
+        .iter()
+        .map(|x| x.items.iter().map(|i| i.entry.clone().unwrap()).collect())
+        .collect();
+
+    close_stale(&mut worklogs, close_after);
+    for worklog in worklogs.iter_mut() {
+        worklog.retain(|x| x.end != Some(x.start));
+    }
+    split_overlaps(&mut worklogs);
+
+    let mut output = vec!();
+    for (x, worklog) in scanned.into_iter().zip(worklogs) {
+        let current: Vec<&str> = x.items.iter().map(|i| i.raw.as_str()).collect();
+        let fixed: Vec<String> = worklog.iter().map(|e| e.to_string()).collect();
+        if current == fixed {
+            continue;
+        }
+        let metadata = Metadata { worklog, ..x.metadata.unwrap() };
+        output.push(DiaryDoc { metadata, path: x.path });
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Worklogs from `START,END` pairs written as `HH:MM` on 2022-10-11,
+    // at +02:00 unless the time comes with its own offset.
+    fn worklogs(docs: &[&[&str]]) -> Vec<Vec<WorklogEntry>> {
+        let ts = |x: &str| match x {
+            "" => String::new(),
+            _ if x.len() > 5 => format!("2022-10-11T{}:00{}", &x[..5], &x[5..]),
+            _ => format!("2022-10-11T{}:00+02:00", x)
+        };
+        docs.iter()
+            .map(|x| x.iter().map(|e| {
+                let (start, end) = e.split_once(',').unwrap();
+                format!("{},{}", ts(start), ts(end)).parse().unwrap()
+            }).collect())
+            .collect()
+    }
+
+    fn strings(docs: &[Vec<WorklogEntry>]) -> Vec<Vec<String>> {
+        docs.iter().map(|x| x.iter().map(|e| e.to_string()).collect()).collect()
+    }
+
+    fn split(docs: &[&[&str]]) -> Vec<Vec<String>> {
+        let mut docs = worklogs(docs);
+        split_overlaps(&mut docs);
+        strings(&docs)
+    }
+
+    #[test]
+    fn nested_overlaps() {
+        assert_eq!(
+            split(&[&["10:00,14:00"], &["11:00,13:00"], &["11:30,12:00"]]),
+            strings(&worklogs(&[
+                &["10:00,11:00", "13:00,14:00"],
+                &["11:00,11:30", "12:00,13:00"],
+                &["11:30,12:00"]
+            ]))
+        );
+    }
+
+    #[test]
+    fn ties_go_to_later_path() {
+        assert_eq!(
+            split(&[&["10:00,12:00"], &["10:00,11:00"]]),
+            strings(&worklogs(&[&["11:00,12:00"], &["10:00,11:00"]]))
+        );
+    }
+
+    #[test]
+    fn fully_covered_entry_is_dropped() {
+        assert_eq!(
+            split(&[&["10:00,11:00", "12:00,13:00"], &["10:00,11:00"]]),
+            strings(&worklogs(&[&["12:00,13:00"], &["10:00,11:00"]]))
+        );
+    }
+
+    #[test]
+    fn split_pieces_keep_offset() {
+        assert_eq!(
+            split(&[&["10:00,13:00"], &["09:00+00:00,10:00+00:00"]]),
+            strings(&worklogs(&[
+                &["10:00,11:00", "12:00,13:00"],
+                &["09:00+00:00,10:00+00:00"]
+            ]))
+        );
+    }
+
+    #[test]
+    fn open_entries_across_docs() {
+        let doc = |path: &str, entries: &str| scan(
+            path.to_string(), &format!("---\ntags: []\nworklog:\n{}---\n", entries)
+        );
+        let scanned = vec!(
+            doc("A-1.md", "- 2022-10-11T10:00:00+02:00,\n"),
+            doc("A-2.md", "- 2022-10-11T09:00:00+02:00,\n- 2022-10-11T11:00:00+02:00,\n")
+        );
+        let found: Vec<String> = multiple_open(&scanned).iter().map(|x| x.to_string()).collect();
+        assert_eq!(found, vec!(
+            "A-1.md: entry 2022-10-11T10:00:00+02:00, is still open, \
+             2022-10-11T11:00:00+02:00, of A-2.md was opened later",
+            "A-2.md: entry 2022-10-11T09:00:00+02:00, is still open, \
+             2022-10-11T11:00:00+02:00, was opened later"
+        ));
+    }
+
+    #[test]
+    fn open_entry_closed_at_next_open_one() {
+        let mut docs = worklogs(&[&["10:00,"], &["11:00,"]]);
+        close_stale(&mut docs, Duration::hours(8));
+        assert_eq!(docs, worklogs(&[&["10:00,11:00"], &["11:00,19:00"]]));
+    }
+
+    #[test]
+    fn recent_last_open_entry_stays_open() {
+        let start = now() - Duration::hours(1);
+        let earlier = start - Duration::hours(1);
+        let mut docs = vec!(vec!(WorklogEntry::new(earlier)), vec!(WorklogEntry::new(start)));
+        close_stale(&mut docs, Duration::hours(8));
+        assert_eq!(docs[0][0].end, Some(start));
+        assert_eq!(docs[1][0].end, None);
+    }
+}
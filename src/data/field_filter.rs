@@ -0,0 +1,73 @@
+use std::{error::Error, fmt, str::FromStr};
+use regex::Regex;
+use super::model::Metadata;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Eq(String),
+    Ne(String),
+    Match(Regex)
+}
+
+/// Condition over any frontmatter field, known or not, such as
+/// `status=done`, `priority!=low` or `sprint~2022-4.*`.
+///
+/// For list fields, `=` and `~` match when any item does and `!=` when
+/// none does. A missing field never matches `=` or `~` and always matches
+/// `!=`. `~` takes a regular expression searched anywhere in the value.
+#[derive(Debug, Clone)]
+pub struct FieldFilter {
+    field: String,
+    op: Op
+}
+
+impl FieldFilter {
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let values = metadata.field_values(&self.field);
+        match &self.op {
+            Op::Eq(x) => values.contains(x),
+            Op::Ne(x) => !values.contains(x),
+            Op::Match(re) => values.iter().any(|x| re.is_match(x))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldFilterError(String);
+
+impl fmt::Display for FieldFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for FieldFilterError {}
+
+impl FromStr for FieldFilter {
+    type Err = FieldFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let idx = s.find(['=', '~']).ok_or_else(|| FieldFilterError(
+            format!("Expected field=value, field!=value or field~regex, found '{}'", s)
+        ))?;
+        let value = &s[idx + 1..];
+        let (field, op) = if s[idx..].starts_with('~') {
+            let re = Regex::new(value)
+                .map_err(|e| FieldFilterError(format!("Invalid regex: {}", e)))?;
+            (&s[..idx], Op::Match(re))
+        } else if s[..idx].ends_with('!') {
+            (&s[..idx - 1], Op::Ne(value.to_string()))
+        } else {
+            (&s[..idx], Op::Eq(value.to_string()))
+        };
+        let field = field.trim();
+        if field.is_empty() {
+            return Err(FieldFilterError(format!("Missing field name in '{}'", s)));
+        }
+        Ok(FieldFilter { field: field.to_string(), op })
+    }
+}
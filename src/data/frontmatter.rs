@@ -0,0 +1,312 @@
+use super::worklog::WorklogEntry;
+
+const KEY: &str = "worklog:";
+
+// A line inside the `worklog` block
+enum BlockLine<'a> {
+    // With what it is matched against new entries by, see `patch_worklog`
+    Item(&'a str, Option<String>),
+
+    // Item that isn't a valid entry, kept as it is
+    Opaque(&'a str),
+    Other(&'a str)
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_other(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+fn item_value(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    if trimmed == "-" {
+        return Some("");
+    }
+    trimmed.strip_prefix("- ")
+}
+
+fn item_scalar(line: &str) -> Option<String> {
+    serde_yaml::from_str(item_value(line)?).ok()
+}
+
+fn item_line(indent: &str, entry: &WorklogEntry, eol: &str) -> Option<String> {
+    let scalar = serde_yaml::to_string(&entry.to_string()).ok()?;
+    Some(format!("{}- {}{}", indent, scalar.trim_end(), eol))
+}
+
+// Longest common subsequence between old and new entries, returns for each
+// old entry the index of the new one it is kept as. Old ones without a key
+// are never kept.
+fn lcs(old: &[Option<String>], new: &[String]) -> Vec<Option<usize>> {
+    let same = |a: &Option<String>, b: &String| a.as_ref() == Some(b);
+    let (n, m) = (old.len(), new.len());
+    let mut table = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if same(&old[i], &new[j]) {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+    let mut out = vec![None; n];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if same(&old[i], &new[j]) {
+            out[i] = Some(j);
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Replaces the `worklog` sequence of `content`'s frontmatter with
+/// `entries`, leaving every other line as it is. Lines of entries that
+/// didn't change are kept verbatim.
+///
+/// Items that aren't valid entries are kept in place too, so a typo in one
+/// of them doesn't get in the way of editing the others.
+///
+/// With `normalize` set only lines already in canonical form count as
+/// unchanged, any other is written again in canonical form (or dropped),
+/// current ones don't even need to parse.
+///
+/// Returns `None` when the block doesn't look like something that can be
+/// edited line by line (flow sequences, multi-line scalars...).
+pub fn patch_worklog(
+    content: &str, entries: &[WorklogEntry], normalize: bool
+) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if lines.first()?.trim() != "---" {
+        return None;
+    }
+    let fm_end = lines.iter().skip(1).position(|x| x.trim() == "---")? + 1;
+    let key_idx = (1..fm_end).find(|i| lines[*i].starts_with(KEY))?;
+    let key_line = lines[key_idx];
+    let eol = if key_line.ends_with("\r\n") { "\r\n" } else { "\n" };
+
+    let key_rest = key_line[KEY.len()..].trim();
+    let key_value = if key_rest.starts_with('#') {
+        ""
+    } else {
+        key_rest.split(" #").next().unwrap().trim()
+    };
+    if !key_value.is_empty() && key_value != "[]" {
+        return None;
+    }
+    let key_comment = key_rest
+        .find('#')
+        .map(|i| format!(" {}", &key_rest[i..]))
+        .unwrap_or_default();
+
+    let mut block: Vec<BlockLine> = vec!();
+    let mut item_indent: Option<usize> = None;
+    let mut cursor = key_idx + 1;
+    while cursor < fm_end {
+        let line = lines[cursor];
+        if is_other(line) {
+            block.push(BlockLine::Other(line));
+        } else if item_value(line).is_some()
+            && item_indent.unwrap_or(indent_of(line)) == indent_of(line)
+        {
+            item_indent = Some(indent_of(line));
+            if normalize {
+                block.push(BlockLine::Item(line, item_scalar(line)));
+            } else {
+                match item_scalar(line)?.parse::<WorklogEntry>() {
+                    Ok(entry) => block.push(BlockLine::Item(line, Some(entry.to_string()))),
+                    Err(_) => block.push(BlockLine::Opaque(line))
+                }
+            }
+        } else if indent_of(line) == 0 {
+            break;
+        } else {
+            return None;
+        }
+        cursor += 1;
+    }
+    // Trailing blank lines and comments belong to what follows
+    while let Some(BlockLine::Other(_)) = block.last() {
+        block.pop();
+        cursor -= 1;
+    }
+    if key_value == "[]" && !block.is_empty() {
+        return None;
+    }
+
+    let old: Vec<Option<String>> = block
+        .iter()
+        .filter_map(|x| match x {
+            BlockLine::Item(_, key) => Some(key.clone()),
+            _ => None
+        })
+        .collect();
+    let new: Vec<String> = entries.iter().map(|x| x.to_string()).collect();
+    let kept = lcs(&old, &new);
+    let indent = " ".repeat(item_indent.unwrap_or(0));
+
+    let mut out: Vec<String> = lines[..key_idx].iter().map(|x| x.to_string()).collect();
+    if entries.is_empty() {
+        out.push(format!("worklog: []{}{}", key_comment, eol));
+    } else if key_value == "[]" {
+        out.push(format!("worklog:{}{}", key_comment, eol));
+    } else {
+        out.push(key_line.to_string());
+    }
+    // New entries go in place of the first old one dropped before the next
+    // kept one, or right before that kept one
+    let mut next_kept = vec![entries.len(); kept.len()];
+    let mut next = entries.len();
+    for (i, x) in kept.iter().enumerate().rev() {
+        next = x.unwrap_or(next);
+        next_kept[i] = next;
+    }
+    let mut next_new = 0;
+    let mut old_idx = 0;
+    for line in block.iter() {
+        match line {
+            BlockLine::Other(x) | BlockLine::Opaque(x) => out.push(x.to_string()),
+            BlockLine::Item(x, _) => {
+                let upto = kept[old_idx].unwrap_or(next_kept[old_idx]);
+                for entry in &entries[next_new..upto] {
+                    out.push(item_line(&indent, entry, eol)?);
+                }
+                next_new = upto;
+                if kept[old_idx].is_some() {
+                    out.push(x.to_string());
+                    next_new += 1;
+                }
+                old_idx += 1;
+            }
+        }
+    }
+    for entry in &entries[next_new..] {
+        out.push(item_line(&indent, entry, eol)?);
+    }
+    out.extend(lines[cursor..].iter().map(|x| x.to_string()));
+    Some(out.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "2022-10-11T10:00:00+02:00,2022-10-11T11:00:00+02:00";
+    const B: &str = "2022-10-12T10:00:00+02:00,2022-10-12T11:00:00+02:00";
+    const C: &str = "2022-10-13T10:00:00+02:00,2022-10-13T11:00:00+02:00";
+
+    fn entries(raw: &[&str]) -> Vec<WorklogEntry> {
+        raw.iter().map(|x| x.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn unchanged_is_verbatim() {
+        let content = concat!(
+            "---\n# top\nauthor: Jane\nworklog:  # sessions\n  # first\n",
+            "  - \"2022-10-11T10:00:00+02:00,2022-10-11T11:00:00+02:00\"  # quoted\n\n",
+            "  - 2022-10-12T10:00:00+02:00, 2022-10-12T11:00:00+02:00\n",
+            "tags: [a]\n---\nbody\n"
+        );
+        let patched = patch_worklog(content, &entries(&[A, B]), false).unwrap();
+        assert_eq!(patched, content);
+    }
+
+    #[test]
+    fn appends_after_last_item() {
+        let content = format!("---\nworklog:\n- {}  # keep\n\n# about tags\ntags: []\n---\n", A);
+        let patched = patch_worklog(&content, &entries(&[A, B]), false).unwrap();
+        assert_eq!(
+            patched,
+            format!("---\nworklog:\n- {}  # keep\n- {}\n\n# about tags\ntags: []\n---\n", A, B)
+        );
+    }
+
+    #[test]
+    fn inserts_mid_block_keeping_indent() {
+        let content = format!("---\nworklog:\n  - {}\n  # between\n  - {}\n---\n", A, C);
+        let patched = patch_worklog(&content, &entries(&[A, B, C]), false).unwrap();
+        assert_eq!(
+            patched,
+            format!("---\nworklog:\n  - {}\n  # between\n  - {}\n  - {}\n---\n", A, B, C)
+        );
+    }
+
+    #[test]
+    fn removes_only_changed_items() {
+        let content = format!("---\nworklog:\n- {}  # a\n- {}  # b\n- {}  # c\n---\n", A, B, C);
+        let patched = patch_worklog(&content, &entries(&[A, C]), false).unwrap();
+        assert_eq!(patched, format!("---\nworklog:\n- {}  # a\n- {}  # c\n---\n", A, C));
+    }
+
+    #[test]
+    fn keeps_crlf() {
+        let content = format!("---\r\nauthor: Jane\r\nworklog:\r\n- {}\r\n---\r\nbody\r\n", A);
+        let patched = patch_worklog(&content, &entries(&[A, B]), false).unwrap();
+        assert_eq!(
+            patched,
+            format!("---\r\nauthor: Jane\r\nworklog:\r\n- {}\r\n- {}\r\n---\r\nbody\r\n", A, B)
+        );
+    }
+
+    #[test]
+    fn empty_worklog() {
+        let content = "---\nworklog: [] # none yet\nauthor: Jane\n---\n";
+        let patched = patch_worklog(content, &entries(&[A]), false).unwrap();
+        assert_eq!(patched, format!("---\nworklog: # none yet\n- {}\nauthor: Jane\n---\n", A));
+        assert_eq!(patch_worklog(&patched, &[], false).unwrap(), content);
+    }
+
+    #[test]
+    fn keeps_invalid_items() {
+        let content = format!("---\nworklog:\n- {}\n- 2022-10-11T1100:00,  # typo\n---\n", A);
+        let patched = patch_worklog(&content, &entries(&[A, B]), false).unwrap();
+        assert_eq!(
+            patched,
+            format!("---\nworklog:\n- {}\n- 2022-10-11T1100:00,  # typo\n- {}\n---\n", A, B)
+        );
+        let patched = patch_worklog(&content, &entries(&[B]), false).unwrap();
+        assert_eq!(
+            patched,
+            format!("---\nworklog:\n- {}\n- 2022-10-11T1100:00,  # typo\n---\n", B)
+        );
+    }
+
+    #[test]
+    fn changed_entry_keeps_its_place() {
+        let content = format!("---\nworklog:\n- {}\n- not an entry\n- {}\n---\n", A, C);
+        let patched = patch_worklog(&content, &entries(&[B, C]), false).unwrap();
+        assert_eq!(patched, format!("---\nworklog:\n- {}\n- not an entry\n- {}\n---\n", B, C));
+    }
+
+    #[test]
+    fn normalize_rewrites_only_changed_lines() {
+        let content = concat!(
+            "---\nworklog:\n",
+            "- 2022-10-11T10:00:00+02:00, 2022-10-11T11:00:00+02:00\n",
+            "- not an entry\n",
+            "- 2022-10-12T10:00:00+02:00,2022-10-12T11:00:00+02:00  # late\n",
+            "- 2022-10-13T10:00:00+02:00,2022-10-13T10:00:00+02:00\n---\n"
+        );
+        let patched = patch_worklog(content, &entries(&[A, B]), true).unwrap();
+        assert_eq!(patched, format!("---\nworklog:\n- {}\n- {}  # late\n---\n", A, B));
+    }
+
+    #[test]
+    fn gives_up_on_other_layouts() {
+        let flow = format!("---\nworklog: [\"{}\"]\n---\n", A);
+        assert!(patch_worklog(&flow, &entries(&[A, B]), false).is_none());
+        let multi_line = format!("---\nworklog:\n- >\n    {}\n---\n", A);
+        assert!(patch_worklog(&multi_line, &entries(&[A, B]), false).is_none());
+        assert!(patch_worklog("author: Jane\n", &entries(&[A]), false).is_none());
+    }
+}
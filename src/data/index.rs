@@ -0,0 +1,142 @@
+use std::{collections::HashMap, fs, io, path::PathBuf, time::SystemTime};
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use walkdir::DirEntry;
+use super::model::Metadata;
+use super::write_atomic;
+use crate::config;
+use crate::error::{Error, Result};
+
+// Bump whenever stored data changes its shape
+const VERSION: u32 = 1;
+
+/// What a file looked like when it was indexed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Stamp {
+    mtime: SystemTime,
+    size: u64
+}
+
+impl Stamp {
+    pub fn of(entry: &DirEntry) -> Option<Stamp> {
+        let meta = entry.metadata().ok()?;
+        Some(Stamp { mtime: meta.modified().ok()?, size: meta.len() })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexEntry {
+    stamp: Stamp,
+    metadata: Metadata
+}
+
+/// On-disk cache of parsed frontmatters keyed by path, so only files whose
+/// mtime or size changed since last run are parsed again.
+///
+/// It lives under `$XDG_CACHE_HOME/xfel-worklog` (`~/.cache` when unset),
+/// one file per diary root. Problems reading or writing it are only logged,
+/// the diary is then parsed as if there were no index.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Index {
+    version: u32,
+    entries: HashMap<String, IndexEntry>,
+
+    #[serde(skip)]
+    dirty: bool
+}
+
+/// Index file for diary at `root`.
+pub fn file_path(root: &str) -> Option<PathBuf> {
+    config::cache_file(root, "index", "json")
+}
+
+/// Deletes index file for diary at `root`, if any.
+pub fn remove(root: &str) -> Result<()> {
+    if let Some(path) = file_path(root) {
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(Error::Io(path.to_string_lossy().to_string(), e));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Index {
+    pub fn load(root: &str) -> Index {
+        let path = match file_path(root) {
+            Some(p) => p,
+            None => return Index::default()
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(_) => return Index::default()
+        };
+        match serde_json::from_str::<Index>(&content) {
+            Ok(index) if index.version == VERSION => index,
+            Ok(_) => {
+                info!("Discarding index with old format: {}", path.display());
+                Index::default()
+            }
+            Err(e) => {
+                warn!("Discarding unreadable index {}: {}", path.display(), e);
+                Index::default()
+            }
+        }
+    }
+
+    pub fn get(&self, path: &str, stamp: &Stamp) -> Option<Metadata> {
+        self.entries
+            .get(path)
+            .filter(|x| &x.stamp == stamp)
+            .map(|x| x.metadata.clone())
+    }
+
+    pub fn insert(&mut self, path: &str, stamp: Stamp, metadata: &Metadata) {
+        // Some yaml values (tags, non string keys) don't survive a trip
+        // through json, those files are just not cached.
+        let roundtrip = serde_json::to_value(metadata)
+            .and_then(serde_json::from_value::<Metadata>);
+        match roundtrip {
+            Ok(x) if &x == metadata => {
+                let entry = IndexEntry { stamp, metadata: x };
+                self.entries.insert(path.to_string(), entry);
+            }
+            _ => {
+                self.entries.remove(path);
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Drops entries whose path doesn't satisfy `keep`.
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        let before = self.entries.len();
+        self.entries.retain(|k, _| keep(k));
+        self.dirty = self.dirty || before != self.entries.len();
+    }
+
+    /// Writes the index back if anything changed.
+    pub fn save(&mut self, root: &str) {
+        if !self.dirty {
+            return;
+        }
+        let path = match file_path(root) {
+            Some(p) => p,
+            None => return
+        };
+        self.version = VERSION;
+        let result = path.parent()
+            .map(fs::create_dir_all)
+            .transpose()
+            .map_err(|e| Error::Io(path.to_string_lossy().to_string(), e))
+            .and_then(|_| {
+                let content = serde_json::to_string(&self).unwrap();
+                write_atomic(&path.to_string_lossy(), &content)
+            });
+        match result {
+            Ok(_) => self.dirty = false,
+            Err(e) => warn!("Couldn't write index: {}", e)
+        }
+    }
+}
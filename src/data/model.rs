@@ -1,17 +1,72 @@
-use chrono::Local;
-use std::{io::{Error, ErrorKind}, cmp::Ordering};
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Timelike};
 use std::ops::Range;
 use std::fmt;
+use std::path::Path;
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Serialize, Deserialize};
+use serde_yaml::{Mapping, Value};
+use super::worklog::WorklogEntry;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Metadata {
     pub author: Option<String>,
     pub date: Option<String>,
     pub tags: Vec<String>,
     pub estimate: Option<String>,
-    pub worklog: Vec<String>
+    pub worklog: Vec<WorklogEntry>,
+
+    /// Any other key found in frontmatter, kept in its original order so
+    /// it is written back untouched.
+    #[serde(flatten)]
+    pub extra: Mapping
+}
+
+impl Metadata {
+    /// Frontmatter's `date` as a date, when it starts with one in ISO
+    /// format (`2022-10-11`, `2022-10-11T10:00:00`...).
+    pub fn doc_date(&self) -> Option<NaiveDate> {
+        let date = self.date.as_ref()?;
+        NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+    }
+
+    /// Values of field `name` as strings, one per item for lists. Empty
+    /// when the field is missing, null or a mapping.
+    pub fn field_values(&self, name: &str) -> Vec<String> {
+        let known = |x: &Option<String>| x.iter().cloned().collect();
+        match name {
+            "author" => known(&self.author),
+            "date" => known(&self.date),
+            "estimate" => known(&self.estimate),
+            "tags" => self.tags.clone(),
+            "worklog" => self.worklog.iter().map(|x| x.to_string()).collect(),
+            _ => match self.extra.get(name) {
+                Some(Value::Sequence(items)) => {
+                    items.iter().filter_map(scalar_to_string).collect()
+                }
+                Some(x) => scalar_to_string(x).into_iter().collect(),
+                None => vec!()
+            }
+        }
+    }
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(x) => Some(x.clone()),
+        Value::Number(x) => Some(x.to_string()),
+        Value::Bool(x) => Some(x.to_string()),
+        Value::Tagged(x) => scalar_to_string(&x.value),
+        _ => None
+    }
+}
+
+/// Whether `s` looks like a Jira issue key: `ABC-123`, `MY_PROJ-12`.
+pub fn is_jira_key(s: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^[A-Z][A-Z0-9_]*-[0-9]+$").unwrap();
+    }
+    RE.is_match(s)
 }
 
 #[derive(Clone)]
@@ -20,27 +75,31 @@ pub struct DiaryDoc {
     pub path: String
 }
 
-impl DiaryDoc {
-    fn is_worklog_entry_complete(&self, entry: &Vec<&str>) -> bool {
-        entry.len() > 1 && !entry[1].is_empty()
-    }
+pub(crate) fn now() -> DateTime<FixedOffset> {
+    let now = Local::now().with_nanosecond(0).unwrap();
+    now.with_timezone(now.offset())
+}
 
+impl DiaryDoc {
     pub fn is_archive(&self) -> bool {
         self.path.contains("_archive")
     }
 
+    /// File name without extension when it is a Jira key (see
+    /// [`is_jira_key`]), as for docs created by `fetch`.
+    pub fn jira_key(&self) -> Option<String> {
+        let stem = Path::new(&self.path).file_stem()?.to_string_lossy();
+        Some(stem.to_string()).filter(|x| is_jira_key(x))
+    }
+
     pub fn is_active(&self) -> bool {
-        for x in &self.metadata.worklog {
-            if !self.is_worklog_entry_complete(&self.worklog_entry(&x)) {
-                return true;
-            }
-        }
-        false
+        self.metadata.worklog.iter().any(|x| !x.is_complete())
     }
 
+    /// `datetime` is compared against each entry's wall time in its own offset.
     pub fn has_work_after(&self, datetime: &NaiveDateTime) -> bool {
         for range in self.worklog_range() {
-            if &range.end > datetime {
+            if &range.end.naive_local() > datetime {
                 return true;
             }
         }
@@ -49,69 +108,50 @@ impl DiaryDoc {
 
     pub fn has_work_before(&self, datetime: &NaiveDateTime) -> bool {
         for range in self.worklog_range() {
-            if &range.start < datetime {
+            if &range.start.naive_local() < datetime {
                 return true;
             }
         }
         false
     }
 
-    pub fn worklog_range(&self) -> Vec<Range<NaiveDateTime>> {
-        let mut out = self.metadata.worklog
+    /// Complete worklog entries sorted by start.
+    pub fn complete_worklog(&self) -> Vec<&WorklogEntry> {
+        let mut out: Vec<&WorklogEntry> = self.metadata.worklog
             .iter()
-            .filter_map(|x| self.worklog_to_date_range(x).ok())
-            .collect::<Vec<Range<NaiveDateTime>>>();
-
-        out.sort_by(|a, b| if a.start > b.start {
-            Ordering::Greater
-        } else if a.start < b.start {
-            Ordering::Less
-        } else {
-            Ordering::Equal
-        });
-
+            .filter(|x| x.is_complete())
+            .collect();
+        out.sort_by_key(|x| x.start);
         out
     }
 
-    fn worklog_entry<'a >(&self, worklog: &'a str) -> Vec<&'a str> {
-        worklog.split(",").map(|x| x.trim()).collect()
-    }
-
-    pub fn worklog_to_date_range(
-        &self, worklog: &str
-    ) -> Result<Range<NaiveDateTime>, Box<dyn std::error::Error>> {
-        let entry = self.worklog_entry(worklog);
-        if !self.is_worklog_entry_complete(&entry) {
-            return Err(
-                Box::new(
-                    Error::new(
-                        ErrorKind::InvalidInput, "Invalid input string"
-                    )
-                )
-            );
-        }
-        let fmt = "%Y-%m-%dT%H:%M:%S";
-        let st = NaiveDateTime::parse_from_str(entry[0], fmt)?;
-        let end = NaiveDateTime::parse_from_str(entry[1], fmt)?;
-        Ok(Range { start: st, end })
+    pub fn worklog_range(&self) -> Vec<Range<DateTime<FixedOffset>>> {
+        self.complete_worklog()
+            .iter()
+            .filter_map(|x| x.range())
+            .collect()
     }
 
     pub fn start(&mut self) {
-        let new_entry = format!(
-            "{},", 
-            Local::now().format("%Y-%m-%dT%H:%M:%S")
-            );
-        self.metadata.worklog.push(new_entry);
+        self.metadata.worklog.push(WorklogEntry::new(now()));
     }
 
     pub fn stop(&mut self) {
-        let mut last_entry = self.metadata.worklog.pop().unwrap();
-        last_entry = format!(
-            "{}{}",
-            last_entry,
-            &mut Local::now().format("%Y-%m-%dT%H:%M:%S")
-            );
-        self.metadata.worklog.push(last_entry);
+        self.stop_with_note(None);
+    }
+
+    /// Stops the open entry attaching `note` to it.
+    pub fn stop_with_note(&mut self, note: Option<String>) {
+        let open = self.metadata.worklog
+            .iter_mut()
+            .rev()
+            .find(|x| !x.is_complete());
+        if let Some(entry) = open {
+            entry.end = Some(now());
+            if note.is_some() {
+                entry.note = note;
+            }
+        }
     }
 }
 
@@ -1,110 +1,353 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use std::cmp::Ordering;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use rayon::prelude::*;
+use regex::Regex;
+use super::field_filter::FieldFilter;
 use super::model::DiaryDoc;
-use super::load_diary;
+use super::search::body_matches;
+use super::tag_expr::TagExpr;
+use super::{conf, load_diary_with_archive};
+use crate::config;
+use crate::error::Result;
 
-fn sort_by_date(data: &mut Vec<DiaryDoc>) {
+fn sort_by_date(data: &mut [DiaryDoc]) {
     data.sort_by_key(|x| {
-        let range = x.worklog_range();
-        if range.len() < 1 {
+        let worklog = x.complete_worklog();
+        if worklog.is_empty() {
             0
         } else {
-            range[0].start.timestamp()
+            worklog[0].start.timestamp()
         }
     });
 }
 
-pub fn all() -> Vec<DiaryDoc> {
-    load_diary().clone()
+fn sort_by_field(data: &mut [DiaryDoc], field: &str) {
+    data.sort_by_cached_key(|x| {
+        let value = x.metadata.field_values(field).into_iter().next();
+        let number = value.as_ref().and_then(|x| x.parse::<f64>().ok());
+        (value.is_none(), number.is_none(), number.map(SortNumber), value)
+    });
 }
 
-pub fn active() -> Vec<DiaryDoc> {
-    let mut data = load_diary();
-    data.retain(|x| x.is_active());
-    data
+// f64 ordered with `total_cmp`, so it can be part of a sort key
+struct SortNumber(f64);
+
+impl PartialEq for SortNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
 }
 
-pub fn by_tags(tags: Vec<String>) -> Vec<DiaryDoc> {
-    let mut data: Vec<DiaryDoc> = load_diary();
-    data.retain(|x| {
-        let mut item_tags = x.metadata.tags.clone();
-        item_tags.retain(|y| tags.contains(y));
-        item_tags.len() > 0
-    });
-    data
+impl Eq for SortNumber {}
+
+impl PartialOrd for SortNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Filters over the diary. Every predicate set must match for a doc to be
+/// returned, unset ones match everything.
+///
+/// ```no_run
+/// use xfel_worklog::data::query::Query;
+///
+/// let docs = Query::new()
+///     .tags(vec!(String::from("cool")))
+///     .path("clientA/")
+///     .active()
+///     .run();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    tags: Option<Vec<String>>,
+    tag_expr: Option<TagExpr>,
+    path: Option<String>,
+    exact_paths: Option<Vec<String>>,
+    date_range: Option<(NaiveDate, Option<NaiveDate>)>,
+    whole_sessions: bool,
+    day_start: Option<u32>,
+    grep: Option<Regex>,
+    author: Option<String>,
+    has_estimate: Option<bool>,
+    doc_date_range: Option<(Option<NaiveDate>, Option<NaiveDate>)>,
+    fields: Vec<FieldFilter>,
+    sort_by: Option<String>,
+    active: bool,
+    include_archive: Option<bool>
+}
+
+impl Query {
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// Docs sharing at least one tag with `tags`.
+    pub fn tags(mut self, tags: Vec<String>) -> Query {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Docs whose tags satisfy `expr`.
+    pub fn tag_expr(mut self, expr: TagExpr) -> Query {
+        self.tag_expr = Some(expr);
+        self
+    }
+
+    /// Docs whose path contains `path`.
+    pub fn path(mut self, path: &str) -> Query {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Docs whose path is exactly one of `paths`.
+    pub fn exact_paths(mut self, paths: Vec<String>) -> Query {
+        self.exact_paths = Some(paths);
+        self
+    }
+
+    /// Docs with worklog inside the given date range, both days included.
+    /// Worklog entries outside of it are removed from returned docs and
+    /// those straddling its bounds are clipped to it, so totals add up
+    /// exactly. Days start at [`Query::day_start`], the same boundary
+    /// reports split sessions at. Results are sorted by their first entry.
+    pub fn date_range(mut self, start: NaiveDate, end: Option<NaiveDate>) -> Query {
+        self.date_range = Some((start, end));
+        self
+    }
+
+    /// With a date range, days run from `hour` o'clock to the same hour of
+    /// the next one. Taken from config's `day_start` when not set. [`run`]
+    /// fails when `hour` is past 23.
+    ///
+    /// [`run`]: Query::run
+    pub fn day_start(mut self, hour: u32) -> Query {
+        self.day_start = Some(hour);
+        self
+    }
+
+    /// With a date range, keep only entries fully inside it and leave them
+    /// whole instead of clipping those straddling its bounds.
+    pub fn whole_sessions(mut self, whole: bool) -> Query {
+        self.whole_sessions = whole;
+        self
+    }
+
+    /// Docs with at least a line of their body (what follows frontmatter)
+    /// matching `re`.
+    pub fn grep(mut self, re: Regex) -> Query {
+        self.grep = Some(re);
+        self
+    }
+
+    /// Docs whose author contains `author`, ignoring case.
+    pub fn author(mut self, author: &str) -> Query {
+        self.author = Some(author.to_lowercase());
+        self
+    }
+
+    /// Docs with (or without) an estimate.
+    pub fn has_estimate(mut self, has: bool) -> Query {
+        self.has_estimate = Some(has);
+        self
+    }
+
+    /// Docs whose frontmatter `date` is inside the given range, both ends
+    /// included. Docs without a valid `date` don't match.
+    pub fn doc_date_range(
+        mut self, from: Option<NaiveDate>, to: Option<NaiveDate>
+    ) -> Query {
+        self.doc_date_range = Some((from, to));
+        self
+    }
+
+    /// Docs satisfying `filter`, can be called many times.
+    pub fn field(mut self, filter: FieldFilter) -> Query {
+        self.fields.push(filter);
+        self
+    }
+
+    /// Sorts results by the first value of frontmatter field `field`:
+    /// numbers first in numeric order, then text, then docs without it.
+    pub fn sort_by(mut self, field: &str) -> Query {
+        self.sort_by = Some(field.to_string());
+        self
+    }
+
+    /// Docs with an unterminated worklog entry.
+    pub fn active(mut self) -> Query {
+        self.active = true;
+        self
+    }
+
+    /// Whether docs under `_archive` are considered, defaults to config.
+    pub fn include_archive(mut self, include: bool) -> Query {
+        self.include_archive = Some(include);
+        self
+    }
+
+    /// Whether `doc` passes every predicate but the date range, which
+    /// also trims doc's worklog, and grep, which needs to read the file.
+    pub fn matches(&self, doc: &DiaryDoc) -> bool {
+        if let Some(tags) = &self.tags {
+            if !doc.metadata.tags.iter().any(|x| tags.contains(x)) {
+                return false;
+            }
+        }
+        if let Some(expr) = &self.tag_expr {
+            if !expr.matches(&doc.metadata.tags) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if !doc.path.contains(path.as_str()) {
+                return false;
+            }
+        }
+        if let Some(paths) = &self.exact_paths {
+            if !paths.contains(&doc.path) {
+                return false;
+            }
+        }
+        if let Some(author) = &self.author {
+            let doc_author = doc.metadata.author.as_deref().unwrap_or("");
+            if !doc_author.to_lowercase().contains(author.as_str()) {
+                return false;
+            }
+        }
+        if let Some(has) = self.has_estimate {
+            if doc.metadata.estimate.is_some() != has {
+                return false;
+            }
+        }
+        if let Some((from, to)) = &self.doc_date_range {
+            let date = match doc.metadata.doc_date() {
+                Some(x) => x,
+                None => return false
+            };
+            if from.map(|x| date < x).unwrap_or(false)
+                || to.map(|x| date > x).unwrap_or(false)
+            {
+                return false;
+            }
+        }
+        if !self.fields.iter().all(|x| x.matches(&doc.metadata)) {
+            return false;
+        }
+        !self.active || doc.is_active()
+    }
+
+    pub fn run(&self) -> Result<Vec<DiaryDoc>> {
+        let include_archive = match self.include_archive {
+            Some(x) => x,
+            None => conf()?.include_archive.unwrap_or(false)
+        };
+        let day_start = match (self.day_start, self.date_range) {
+            (Some(x), _) => config::check_day_start(x)?,
+            (None, Some(_)) => conf()?.day_start.unwrap_or(0),
+            (None, None) => 0
+        };
+        let mut data = load_diary_with_archive(include_archive)?;
+        data.retain(|x| self.matches(x));
+        if let Some((start, end)) = &self.date_range {
+            data = filter_date(data, start, end, day_start, self.whole_sessions);
+        }
+        if let Some(re) = &self.grep {
+            let keep: Vec<bool> = data
+                .par_iter()
+                .map(|x| body_matches(x, re))
+                .collect::<Result<Vec<bool>>>()?;
+            let mut keep = keep.iter();
+            data.retain(|_| *keep.next().unwrap());
+        }
+        if let Some(field) = &self.sort_by {
+            sort_by_field(&mut data, field);
+        }
+        Ok(data)
+    }
+}
+
+/// Every doc in the diary.
+pub fn all() -> Result<Vec<DiaryDoc>> {
+    Query::new().run()
+}
+
+/// Docs with an unterminated worklog entry.
+pub fn active() -> Result<Vec<DiaryDoc>> {
+    Query::new().active().run()
+}
+
+/// Docs sharing at least one tag with `tags`.
+pub fn by_tags(tags: Vec<String>) -> Result<Vec<DiaryDoc>> {
+    Query::new().tags(tags).run()
 }
 
 pub fn by_tags_and_date(
     tags: Vec<String>, 
     start_date: &NaiveDate, 
     end_date: &Option<NaiveDate>
-) -> Vec<DiaryDoc> {
-    let data: Vec<DiaryDoc> = by_tags(tags);
-    filter_date(data, start_date, end_date)
+) -> Result<Vec<DiaryDoc>> {
+    Query::new().tags(tags).date_range(*start_date, *end_date).run()
 }
 
 fn filter_date(
     mut data: Vec<DiaryDoc>,
     start_date: &NaiveDate, 
-    end_date: &Option<NaiveDate>
+    end_date: &Option<NaiveDate>,
+    day_start: u32,
+    whole_sessions: bool
 ) -> Vec<DiaryDoc> {
-    let st_ts = start_date.and_hms(0, 0, 0);
-    let end_ts: Option<NaiveDateTime> = if let Some(end) = end_date {
-        Some(end.and_hms(23, 59, 59))
-    } else {
-        None
-    };
-    data.retain(|x| {
-        if !x.has_work_after(&st_ts) {
-            return false;
-        }
-        if let Some(end) = end_ts {
-            return x.has_work_before(&end);
-        }
-        true
-    });
+    let st_ts = start_date.and_hms(day_start, 0, 0);
+    // Excluded, so sessions can end right when the next day starts
+    let end_ts: Option<NaiveDateTime> = end_date
+        .map(|end| (end + Duration::days(1)).and_hms(day_start, 0, 0));
     for doc in data.iter_mut() {
-        let mut worklog: Vec<String> = doc.metadata.worklog.to_vec();
-        worklog.retain_mut(|x| {
-            if let Some(range) = doc.worklog_to_date_range(x).ok() {
-                if let Some(end) = end_ts {
-                    return range.start > st_ts && range.end < end;
+        let worklog = std::mem::take(&mut doc.metadata.worklog);
+        doc.metadata.worklog = worklog
+            .into_iter()
+            .filter_map(|x| {
+                let clipped = x.clip(&st_ts, end_ts.as_ref())?;
+                if whole_sessions {
+                    return Some(x).filter(|x| *x == clipped);
                 }
-                return range.start > st_ts;
-            }
-            false
-        });
-        doc.metadata.worklog = worklog;
+                Some(clipped)
+            })
+            .collect();
     }
-    data.retain(|x| x.metadata.worklog.len() > 0);
+    data.retain(|x| !x.metadata.worklog.is_empty());
     sort_by_date(&mut data);
     data
 }
 
+/// Docs with worklog inside the given date range, worklog entries
+/// outside of it are removed from returned docs.
 pub fn by_date(
     start_date: &NaiveDate, 
     end_date: &Option<NaiveDate>
-) -> Vec<DiaryDoc> {
-    let data: Vec<DiaryDoc> = load_diary();
-    filter_date(data, start_date, end_date)
+) -> Result<Vec<DiaryDoc>> {
+    Query::new().date_range(*start_date, *end_date).run()
 }
 
-pub fn by_path_multiple(paths: &Vec<String>) -> Vec<DiaryDoc> {
-    let mut data: Vec<DiaryDoc> = load_diary();
-    data.retain(|x| paths.contains(&x.path));
-    data
+/// Docs whose path is exactly one of `paths`.
+pub fn by_path_multiple(paths: &[String]) -> Result<Vec<DiaryDoc>> {
+    Query::new().exact_paths(paths.to_vec()).run()
 }
 
-pub fn by_path(path: &str) -> Vec<DiaryDoc> {
-    let mut data: Vec<DiaryDoc> = load_diary();
-    data.retain(|x| x.path.contains(path));
-    data
+/// Docs whose path contains `path`.
+pub fn by_path(path: &str) -> Result<Vec<DiaryDoc>> {
+    Query::new().path(path).run()
 }
 
 pub fn by_path_and_date(
     path: &str, 
     start_date: &NaiveDate, 
     end_date: &Option<NaiveDate>
-) -> Vec<DiaryDoc> {
-    let data: Vec<DiaryDoc> = by_path(path);
-    filter_date(data, start_date, end_date)
+) -> Result<Vec<DiaryDoc>> {
+    Query::new().path(path).date_range(*start_date, *end_date).run()
 }
@@ -0,0 +1,73 @@
+use std::fs;
+use regex::Regex;
+use super::model::DiaryDoc;
+use super::io_error;
+use crate::error::Result;
+
+/// Consecutive lines of a doc around one or more matches.
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// `(line number in file, text, whether it matched)`
+    pub lines: Vec<(usize, String, bool)>
+}
+
+/// Lines of doc's body (everything after frontmatter) numbered as in the
+/// file, starting at 1.
+pub fn body_lines(doc: &DiaryDoc) -> Result<Vec<(usize, String)>> {
+    let content = fs::read_to_string(&doc.path).map_err(io_error(&doc.path))?;
+    let lines: Vec<&str> = content.lines().collect();
+    let mut body_start = 0;
+    if lines.first().map(|x| x.trim() == "---").unwrap_or(false) {
+        if let Some(end) = lines.iter().skip(1).position(|x| x.trim() == "---") {
+            body_start = end + 2;
+        }
+    }
+    Ok(
+        lines
+            .iter()
+            .enumerate()
+            .skip(body_start)
+            .map(|(i, x)| (i + 1, x.to_string()))
+            .collect()
+    )
+}
+
+/// Whether any line of doc's body matches `re`.
+pub fn body_matches(doc: &DiaryDoc, re: &Regex) -> Result<bool> {
+    Ok(body_lines(doc)?.iter().any(|(_, x)| re.is_match(x)))
+}
+
+/// Lines of doc's body matching `re` with `context` lines before and after
+/// each of them. Overlapping contexts are merged into the same hunk.
+pub fn search_doc(doc: &DiaryDoc, re: &Regex, context: usize) -> Result<Vec<Hunk>> {
+    let lines = body_lines(doc)?;
+    let matched: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, x))| re.is_match(x))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut ranges: Vec<(usize, usize)> = vec!();
+    for i in matched.iter() {
+        let start = i.saturating_sub(context);
+        let end = (i + context).min(lines.len() - 1);
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = end,
+            _ => ranges.push((start, end))
+        }
+    }
+    Ok(
+        ranges
+            .iter()
+            .map(|(start, end)| Hunk {
+                lines: (*start..=*end)
+                    .map(|i| {
+                        let (n, text) = &lines[i];
+                        (*n, text.to_string(), matched.contains(&i))
+                    })
+                    .collect()
+            })
+            .collect()
+    )
+}
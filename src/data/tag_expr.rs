@@ -0,0 +1,267 @@
+use std::{error::Error, fmt, str::FromStr};
+
+/// Boolean expression over tags, such as
+/// `clientA and (bug or incident) and not internal`.
+///
+/// Operators are `and`, `or` and `not` (from lowest to highest precedence
+/// `or`, `and`, `not`), parentheses group. Tags with spaces or named like
+/// an operator go quoted: `'my tag' or "and"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>)
+}
+
+impl TagExpr {
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpr::Tag(tag) => tags.contains(tag),
+            TagExpr::Not(x) => !x.matches(tags),
+            TagExpr::And(a, b) => a.matches(tags) && b.matches(tags),
+            TagExpr::Or(a, b) => a.matches(tags) || b.matches(tags)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TagExprError {
+    input: String,
+    message: String,
+
+    // In chars from the start of input
+    column: usize
+}
+
+impl fmt::Display for TagExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} at column {}", self.message, self.column + 1)?;
+        writeln!(f, "  {}", self.input)?;
+        write!(f, "  {}^", " ".repeat(self.column))
+    }
+}
+
+impl Error for TagExprError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Tag(String)
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::And => write!(f, "'and'"),
+            Token::Or => write!(f, "'or'"),
+            Token::Not => write!(f, "'not'"),
+            Token::Tag(x) => write!(f, "tag '{}'", x)
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(usize, Token)>,
+    cursor: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: String, column: usize) -> TagExprError {
+        TagExprError { input: self.input.to_string(), message, column }
+    }
+
+    fn end_column(&self) -> usize {
+        self.input.chars().count()
+    }
+
+    fn tokenize(&mut self) -> Result<(), TagExprError> {
+        let chars: Vec<char> = self.input.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '(' || c == ')' {
+                let token = if c == '(' { Token::Open } else { Token::Close };
+                self.tokens.push((i, token));
+                i += 1;
+            } else if c == '\'' || c == '"' {
+                let start = i;
+                i += 1;
+                let mut tag = String::new();
+                while i < chars.len() && chars[i] != c {
+                    tag.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(self.error(String::from("Unterminated quote"), start));
+                }
+                i += 1;
+                self.tokens.push((start, Token::Tag(tag)));
+            } else {
+                let start = i;
+                let mut word = String::new();
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !"()'\"".contains(chars[i])
+                {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                let token = match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Tag(word)
+                };
+                self.tokens.push((start, token));
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor).map(|x| &x.1)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.cursor)
+            .map(|x| x.0)
+            .unwrap_or_else(|| self.end_column())
+    }
+
+    fn or(&mut self) -> Result<TagExpr, TagExprError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.cursor += 1;
+            let right = self.and()?;
+            left = TagExpr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<TagExpr, TagExprError> {
+        let mut left = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.cursor += 1;
+            let right = self.not()?;
+            left = TagExpr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<TagExpr, TagExprError> {
+        if self.peek() == Some(&Token::Not) {
+            self.cursor += 1;
+            return Ok(TagExpr::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<TagExpr, TagExprError> {
+        let column = self.column();
+        match self.peek().cloned() {
+            Some(Token::Tag(tag)) => {
+                self.cursor += 1;
+                Ok(TagExpr::Tag(tag))
+            }
+            Some(Token::Open) => {
+                self.cursor += 1;
+                let inner = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.error(String::from("Unclosed '('"), column));
+                }
+                self.cursor += 1;
+                Ok(inner)
+            }
+            Some(token) => {
+                let msg = format!("Expected a tag, 'not' or '(' but found {}", token);
+                Err(self.error(msg, column))
+            }
+            None => {
+                let msg = String::from("Expected a tag, 'not' or '(' but expression ended");
+                Err(self.error(msg, column))
+            }
+        }
+    }
+}
+
+impl FromStr for TagExpr {
+    type Err = TagExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, tokens: vec!(), cursor: 0 };
+        parser.tokenize()?;
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            let msg = match token {
+                Token::Close => String::from("Unmatched ')'"),
+                Token::Tag(_) | Token::Open | Token::Not => {
+                    format!("Expected 'and' or 'or' before {}", token)
+                }
+                _ => format!("Unexpected {}", token)
+            };
+            return Err(parser.error(msg, parser.column()));
+        }
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(x: &str) -> Box<TagExpr> {
+        Box::new(TagExpr::Tag(x.to_string()))
+    }
+
+    fn error_at(input: &str) -> (String, usize) {
+        let e = input.parse::<TagExpr>().unwrap_err();
+        (e.message, e.column)
+    }
+
+    #[test]
+    fn precedence() {
+        let expr: TagExpr = "a or b and not c".parse().unwrap();
+        let and = TagExpr::And(tag("b"), Box::new(TagExpr::Not(tag("c"))));
+        assert_eq!(expr, TagExpr::Or(tag("a"), Box::new(and)));
+
+        let expr: TagExpr = "(a or b) and c".parse().unwrap();
+        let or = TagExpr::Or(tag("a"), tag("b"));
+        assert_eq!(expr, TagExpr::And(Box::new(or), tag("c")));
+    }
+
+    #[test]
+    fn quoted_operators_are_tags() {
+        let expr: TagExpr = "'and' or \"not\" and 'my tag'".parse().unwrap();
+        let and = TagExpr::And(tag("not"), tag("my tag"));
+        assert_eq!(expr, TagExpr::Or(tag("and"), Box::new(and)));
+        assert!(expr.matches(&[String::from("and")]));
+        assert!(!expr.matches(&[String::from("not")]));
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(error_at("a and 'b"), (String::from("Unterminated quote"), 6));
+        assert_eq!(error_at("a or b)"), (String::from("Unmatched ')'"), 6));
+        assert_eq!(error_at("(a or b"), (String::from("Unclosed '('"), 0));
+        assert_eq!(
+            error_at("a and"),
+            (String::from("Expected a tag, 'not' or '(' but expression ended"), 5)
+        );
+        assert_eq!(
+            error_at("a or and b"),
+            (String::from("Expected a tag, 'not' or '(' but found 'and'"), 5)
+        );
+        assert_eq!(error_at("a b"), (String::from("Expected 'and' or 'or' before tag 'b'"), 2));
+    }
+}
@@ -0,0 +1,191 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{error::Error, fmt, ops::Range, str::FromStr};
+
+const TS_FMT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+const NAIVE_TS_FMT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// A single worklog session.
+///
+/// In frontmatter it is written as `START,STOP` with timestamps in ISO
+/// format, `STOP` is empty while the session is still open:
+/// `2022-10-11T15:44:00+02:00,2022-10-11T15:54:18+02:00` or
+/// `2022-10-11T15:44:00+02:00,`
+///
+/// Timestamps without offset (written by older versions) are read as
+/// local time.
+///
+/// An optional note can follow as a third field:
+/// `2022-10-11T15:44:00+02:00,2022-10-11T15:54:18+02:00,reviewed PR`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorklogEntry {
+    pub start: DateTime<FixedOffset>,
+    pub end: Option<DateTime<FixedOffset>>,
+    pub note: Option<String>
+}
+
+#[derive(Debug)]
+pub struct WorklogParseError(String);
+
+impl fmt::Display for WorklogParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for WorklogParseError {}
+
+impl WorklogEntry {
+    /// Opens a new session at `start`.
+    pub fn new(start: DateTime<FixedOffset>) -> WorklogEntry {
+        WorklogEntry { start, end: None, note: None }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.end.is_some()
+    }
+
+    /// Range covered by the session, `None` while it is still open.
+    pub fn range(&self) -> Option<Range<DateTime<FixedOffset>>> {
+        self.end.map(|end| Range { start: self.start, end })
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.end.map(|end| end - self.start)
+    }
+
+    /// Part of the session between wall times `start` and `end` (excluded),
+    /// read in the entry's own offset. `None` when nothing is left or the
+    /// session is still open.
+    pub fn clip(
+        &self, start: &NaiveDateTime, end: Option<&NaiveDateTime>
+    ) -> Option<WorklogEntry> {
+        let range = self.range()?;
+        let mut clipped = self.clone();
+        if range.start.naive_local() < *start {
+            clipped.start = range.start.offset().from_local_datetime(start).single()?;
+        }
+        if let Some(end) = end.filter(|x| range.end.naive_local() > **x) {
+            clipped.end = Some(range.end.offset().from_local_datetime(end).single()?);
+        }
+        if clipped.end? <= clipped.start {
+            return None;
+        }
+        Some(clipped)
+    }
+
+    /// Day `ts` belongs to when days start at `day_start` o'clock, in
+    /// `ts`'s own offset. Hours past 23 wrap around.
+    pub fn day_of(ts: &DateTime<FixedOffset>, day_start: u32) -> NaiveDate {
+        (ts.naive_local() - Duration::hours((day_start % 24).into())).date()
+    }
+
+    /// The session cut at day boundaries (`day_start` o'clock in its start's
+    /// offset), each piece along with its day. Empty while it is open.
+    /// Hours past 23 wrap around.
+    pub fn split_days(&self, day_start: u32) -> Vec<(NaiveDate, WorklogEntry)> {
+        let day_start = day_start % 24;
+        let end = match self.end {
+            Some(x) => x,
+            None => return vec!()
+        };
+        let offset = *self.start.offset();
+        let mut output = vec!();
+        let mut start = self.start;
+        loop {
+            let day = WorklogEntry::day_of(&start, day_start);
+            let next_day = (day + Duration::days(1)).and_hms(day_start, 0, 0);
+            let boundary = offset.from_local_datetime(&next_day).unwrap();
+            let piece_end = if boundary < end { boundary } else { end };
+            output.push((day, WorklogEntry {
+                start,
+                end: Some(piece_end),
+                note: self.note.clone()
+            }));
+            if piece_end >= end {
+                return output;
+            }
+            start = piece_end;
+        }
+    }
+}
+
+fn parse_ts(s: &str) -> Result<DateTime<FixedOffset>, WorklogParseError> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts);
+    }
+    let naive = NaiveDateTime::parse_from_str(s, NAIVE_TS_FMT)
+        .map_err(|e| WorklogParseError(format!("{}: {}", s, e)))?;
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(ts) => Ok(ts.with_timezone(ts.offset())),
+        None => Err(WorklogParseError(format!("{}: not a valid local time", s)))
+    }
+}
+
+impl FromStr for WorklogEntry {
+    type Err = WorklogParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Note goes last so it can contain commas
+        let parts: Vec<&str> = s.splitn(3, ',').map(|x| x.trim()).collect();
+        let start = parse_ts(parts[0])?;
+        let end = match parts.get(1) {
+            Some(x) if !x.is_empty() => Some(parse_ts(x)?),
+            _ => None
+        };
+        let note = match parts.get(2) {
+            Some(x) if !x.is_empty() => Some(x.to_string()),
+            _ => None
+        };
+        Ok(WorklogEntry { start, end, note })
+    }
+}
+
+impl fmt::Display for WorklogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},", self.start.format(TS_FMT))?;
+        if let Some(end) = self.end {
+            write!(f, "{}", end.format(TS_FMT))?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, ",{}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for WorklogEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for WorklogEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_days_at_day_start() {
+        let entry: WorklogEntry = "2022-10-11T22:00:00+02:00,2022-10-12T06:00:00+02:00"
+            .parse()
+            .unwrap();
+        let pieces: Vec<String> = entry
+            .split_days(4)
+            .iter()
+            .map(|(day, x)| format!("{} {}", day, x))
+            .collect();
+        assert_eq!(pieces, vec!(
+            "2022-10-11 2022-10-11T22:00:00+02:00,2022-10-12T04:00:00+02:00",
+            "2022-10-12 2022-10-12T04:00:00+02:00,2022-10-12T06:00:00+02:00"
+        ));
+        assert_eq!(entry.split_days(28), entry.split_days(4));
+        assert_eq!(entry.split_days(24), entry.split_days(0));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{error, fmt, io};
+use crate::data::worklog::WorklogParseError;
+
+/// Every failure this crate can report.
+///
+/// Variants carry the path or context involved so they can be shown to the
+/// user as is.
+#[derive(Debug)]
+pub enum Error {
+    /// Missing or invalid configuration
+    Config(String),
+
+    /// Filesystem failure on the given path
+    Io(String, io::Error),
+
+    /// Frontmatter of the given path couldn't be parsed
+    Frontmatter(String, String),
+
+    /// A worklog entry of the given path couldn't be parsed
+    Worklog(String, WorklogParseError),
+
+    /// Jira request failed or returned an error status
+    JiraHttp(String),
+
+    /// Jira response couldn't be decoded
+    JiraDecode(String),
+
+    /// The given path isn't named after a Jira ticket
+    JiraKey(String)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Process exit code for this kind of error, one per category.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 3,
+            Error::Io(_, _) => 4,
+            Error::Frontmatter(_, _) => 5,
+            Error::Worklog(_, _) => 6,
+            Error::JiraHttp(_) => 7,
+            Error::JiraDecode(_) => 8,
+            Error::JiraKey(_) => 9
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "Configuration error: {}", msg),
+            Error::Io(path, e) => write!(f, "I/O error on {}: {}", path, e),
+            Error::Frontmatter(path, msg) => {
+                write!(f, "Invalid frontmatter in {}: {}", path, msg)
+            }
+            Error::Worklog(path, e) => {
+                write!(f, "Invalid worklog entry in {}: {}", path, e)
+            }
+            Error::JiraHttp(msg) => write!(f, "Jira request failed: {}", msg),
+            Error::JiraDecode(msg) => {
+                write!(f, "Unexpected Jira response: {}", msg)
+            }
+            Error::JiraKey(path) => {
+                write!(f, "File name of {} is not a Jira key (ABC-123)", path)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            Error::Worklog(_, e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        if e.is_decode() {
+            Error::JiraDecode(e.to_string())
+        } else {
+            Error::JiraHttp(e.to_string())
+        }
+    }
+}
@@ -1,19 +1,14 @@
-use chrono::{DateTime, NaiveDateTime, Duration, offset::Local};
+use chrono::{DateTime, Duration, FixedOffset};
 use reqwest::blocking::Client;
-use std::{error::Error, fmt, cmp::max};
+use std::cmp::max;
 use serde::{Serialize, Deserialize};
 use super::data::model::DiaryDoc;
+use super::config::{self, JiraConfig};
+use super::error::{Error, Result};
 use log::warn;
 
 const JIRA_DATE_FMT: &str = "%Y-%m-%dT%H:%M:%S%.3f%z"; 
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    host: String,
-    user: String,
-    pass: String
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 struct WorklogBody {
     comment: String,
@@ -78,78 +73,59 @@ pub struct JiraTimetracking {
     pub original_estimate: Option<String>
 }
 
-#[derive(Debug)]
-pub struct JiraError(String);
-
-impl fmt::Display for JiraError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "JiraError: {}", self.0)
-    }
-}
-
-impl Error for JiraError {}
-
-fn conf_from_env() -> Config {
-    envy::prefixed("JIRA_").from_env().unwrap()
+fn conf() -> Result<JiraConfig> {
+    config::jira()
 }
 
-pub fn fetch(key: &str) -> Result<JiraTicket, Box<dyn Error>> {
-    let conf = conf_from_env();
+/// Downloads a ticket by its key (`ABC-123`).
+pub fn fetch(key: &str) -> Result<JiraTicket> {
+    let conf = conf()?;
     let response = Client::new()
         .get(format!("{}/rest/api/2/issue/{}", conf.host, key))
         .header("Accept", "application/json")
         .basic_auth(conf.user, Some(conf.pass))
         .send()?;
     if !response.status().is_success() {
-        return Err(Box::new(JiraError(response.text()?)));
+        return Err(Error::JiraHttp(response.text()?));
     }
-    Ok(response.json().unwrap())
+    Ok(response.json()?)
 }
 
 fn worklog_uri(key: &str) -> String {
     format!("/rest/api/2/issue/{}/worklog", key)
 }
 
-pub fn fetch_worklogs(key: &str) -> Result<Vec<JiraWorklog>, Box<dyn Error>> {
-    let conf: Config = conf_from_env();
+/// Downloads current worklogs for a ticket.
+pub fn fetch_worklogs(key: &str) -> Result<Vec<JiraWorklog>> {
+    let conf = conf()?;
     let response = Client::new()
         .get(format!("{}{}", conf.host, worklog_uri(key)))
         .basic_auth(conf.user, Some(conf.pass))
         .send()?;
     if !response.status().is_success() {
-        return Err(Box::new(JiraError(response.text()?)));
+        return Err(Error::JiraHttp(response.text()?));
     }
-    Ok(response.json::<JiraWorklogGetResponse>().unwrap().worklogs)
-}
-
-fn ts_to_string(ts: NaiveDateTime) -> String {
-    ts.and_local_timezone(Local::now().timezone())
-        .unwrap()
-        .format(JIRA_DATE_FMT)
-        .to_string()
+    Ok(response.json::<JiraWorklogGetResponse>()?.worklogs)
 }
 
-fn date_string_to_local_date_string(ts: &str) -> String {
-    DateTime::parse_from_str(&ts, JIRA_DATE_FMT)
-        .unwrap()
-        .with_timezone(&Local::now().timezone())
-        .format(JIRA_DATE_FMT)
-        .to_string()
+fn ts_to_string(ts: &DateTime<FixedOffset>) -> String {
+    ts.format(JIRA_DATE_FMT).to_string()
 }
 
 fn sync_worklog(
     key: &str, 
     started: &str,
-    duration: &Duration
-) -> Result<(), Box<dyn Error>> {
-    let conf = conf_from_env();
+    duration: &Duration,
+    comment: &str
+) -> Result<()> {
+    let conf = conf()?;
     let vars = [
         ("notifyUsers", "false"),
         ("adjustEstimate", "auto"),
         ("overrideEditableFlag", "false")
     ];
     let body = WorklogBody {
-        comment: String::from(""),
+        comment: comment.to_string(),
         started: started.to_string(),
         // timeSpentSeconds cannot be less than 60s
         time_spent_seconds: max(duration.num_seconds(), 60),
@@ -164,35 +140,28 @@ fn sync_worklog(
         .send()?;
     if !response.status().is_success() {
         let msg = format!("{}\n{}", response.status(), response.text()?);
-        return Err(Box::new(JiraError(msg)));
+        return Err(Error::JiraHttp(msg));
     }
     Ok(())
 }
 
-fn jira_key(doc: &DiaryDoc) -> String {
-    let fname = doc.path.rsplit_once("/").unwrap().1;
-    let without_extension = fname.rsplit_once(".").unwrap().0;
-    without_extension.to_string()
-}
-
-pub fn sync_worklogs(doc: DiaryDoc) -> Result<(), Box<dyn Error>> {
-    let key = jira_key(&doc);
-    let current = fetch_worklogs(&key);
-    if let Err(e) = current {
-        return Err(e);
-    }
-    let existing: Vec<String> = current
-        .unwrap()
+/// Creates on Jira every worklog entry from `doc` not already present there.
+/// The ticket key is taken from doc's file name (see [`DiaryDoc::jira_key`]),
+/// docs not named after one are an error.
+pub fn sync_worklogs(doc: DiaryDoc) -> Result<()> {
+    let key = doc.jira_key().ok_or_else(|| Error::JiraKey(doc.path.to_string()))?;
+    let existing: Vec<DateTime<FixedOffset>> = fetch_worklogs(&key)?
         .iter()
-        .map(|x| date_string_to_local_date_string(&x.started))
+        .filter_map(|x| DateTime::parse_from_str(&x.started, JIRA_DATE_FMT).ok())
         .collect();
-    for range in doc.worklog_range() {
-        let started = ts_to_string(range.start);
-        if existing.contains(&started) {
+    for entry in doc.complete_worklog() {
+        let started = ts_to_string(&entry.start);
+        if existing.contains(&entry.start) {
             warn!("Skipping existing entry: {}", started);
             continue
         }
-        sync_worklog(&key, &started, &(range.end - range.start))?;
+        let comment = entry.note.as_deref().unwrap_or("");
+        sync_worklog(&key, &started, &entry.duration().unwrap(), comment)?;
     }
     Ok(())
 }
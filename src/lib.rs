@@ -0,0 +1,18 @@
+//! Library side of xfel-worklog.
+//!
+//! A diary is a directory of markdown files with a yaml frontmatter
+//! (see [`data::model::Metadata`]). This crate exposes loading and
+//! querying of those files, reports on time spent ([`report`]) and the
+//! Jira client used to fetch tickets and sync worklogs, so other tools can
+//! work with a diary without going through the cli.
+
+pub mod config;
+pub mod data;
+pub mod error;
+pub mod jira;
+pub mod report;
+
+pub use data::model::{DiaryDoc, Metadata};
+pub use data::worklog::WorklogEntry;
+pub use data::load_diary;
+pub use error::{Error, Result};
@@ -1,6 +1,4 @@
-mod data;
 mod cli;
-mod jira;
 
 fn main() {
     simple_logger::init_with_env().unwrap();
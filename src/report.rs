@@ -0,0 +1,231 @@
+//! Time spent on docs, computed from their worklogs, ready to be rendered.
+//!
+//! Every output (tables, markdown, json, csv) renders a [`Report`],
+//! so all of them show the same numbers.
+
+use std::collections::{BTreeMap, HashMap};
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+use crate::data::model::DiaryDoc;
+use crate::data::worklog::WorklogEntry;
+
+const DATE_FMT: &str = "%Y-%m-%d";
+
+/// A complete worklog session of a doc, or the part of it inside `day`
+/// when it crosses a day boundary.
+#[derive(Debug, Clone)]
+pub struct Session<'a> {
+    pub doc: &'a DiaryDoc,
+    pub day: NaiveDate,
+    pub entry: WorklogEntry
+}
+
+impl<'a> Session<'a> {
+    pub fn duration(&self) -> Duration {
+        self.entry.duration().unwrap()
+    }
+}
+
+/// Every complete session of `docs`, split at day boundaries (see
+/// [`WorklogEntry::split_days`]) and sorted by start. Sessions starting at
+/// the same time keep `docs` order, none is lost.
+pub fn sessions(docs: &[DiaryDoc], day_start: u32) -> Vec<Session<'_>> {
+    let mut output: Vec<Session> = docs
+        .iter()
+        .flat_map(|doc| {
+            doc.complete_worklog()
+                .into_iter()
+                .flat_map(move |x| x.split_days(day_start))
+                .map(move |(day, entry)| Session { doc, day, entry })
+        })
+        .collect();
+    output.sort_by_key(|x| x.entry.start);
+    output
+}
+
+fn sum<'a>(sessions: impl IntoIterator<Item = &'a Session<'a>>) -> Duration {
+    sessions.into_iter().fold(Duration::zero(), |acc, x| acc + x.duration())
+}
+
+/// Sessions of a day, sorted by start.
+#[derive(Debug, Clone)]
+pub struct Day<'a> {
+    pub date: NaiveDate,
+    pub sessions: Vec<Session<'a>>,
+    pub total: Duration
+}
+
+/// Time spent on a doc, overall and by day.
+#[derive(Debug, Clone)]
+pub struct DocTotal<'a> {
+    pub doc: &'a DiaryDoc,
+    pub days: BTreeMap<NaiveDate, Duration>,
+    pub total: Duration
+}
+
+/// Time spent on docs with a tag.
+#[derive(Debug, Clone)]
+pub struct TagTotal {
+    pub tag: String,
+    pub total: Duration
+}
+
+/// Time spent on a set of docs, by day, by doc and by tag.
+#[derive(Debug, Clone)]
+pub struct Report<'a> {
+    /// Sorted by date.
+    pub days: Vec<Day<'a>>,
+
+    /// In the order docs were given, only those with time spent.
+    pub docs: Vec<DocTotal<'a>>,
+
+    /// Sorted by tag. A session counts for every tag of its doc, so these
+    /// don't add up to `total`.
+    pub tags: Vec<TagTotal>,
+
+    pub total: Duration
+}
+
+impl<'a> Report<'a> {
+    /// Report over every complete session of `docs`, days start at
+    /// `day_start` o'clock (see [`WorklogEntry::split_days`]).
+    pub fn new(docs: &'a [DiaryDoc], day_start: u32) -> Report<'a> {
+        let all = sessions(docs, day_start);
+        let total = sum(&all);
+
+        let mut by_doc: Vec<DocTotal> = docs
+            .iter()
+            .map(|doc| DocTotal { doc, days: BTreeMap::new(), total: Duration::zero() })
+            .collect();
+        let doc_idx: HashMap<&str, usize> = docs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.path.as_str(), i))
+            .collect();
+        for x in all.iter() {
+            let acc = &mut by_doc[doc_idx[x.doc.path.as_str()]];
+            let day = acc.days.entry(x.day).or_insert_with(Duration::zero);
+            *day = *day + x.duration();
+            acc.total = acc.total + x.duration();
+        }
+        by_doc.retain(|x| !x.days.is_empty());
+
+        let mut by_tag: BTreeMap<String, Duration> = BTreeMap::new();
+        for x in by_doc.iter() {
+            for tag in x.doc.metadata.tags.iter() {
+                let acc = by_tag.entry(tag.to_string()).or_insert_with(Duration::zero);
+                *acc = *acc + x.total;
+            }
+        }
+
+        let mut by_day: BTreeMap<NaiveDate, Vec<Session>> = BTreeMap::new();
+        for x in all {
+            by_day.entry(x.day).or_default().push(x);
+        }
+        let days = by_day
+            .into_iter()
+            .map(|(date, sessions)| Day { date, total: sum(&sessions), sessions })
+            .collect();
+
+        Report {
+            days,
+            docs: by_doc,
+            tags: by_tag.into_iter().map(|(tag, total)| TagTotal { tag, total }).collect(),
+            total
+        }
+    }
+
+    /// Every session, by day and then by start.
+    pub fn sessions(&self) -> impl Iterator<Item = &Session<'a>> {
+        self.days.iter().flat_map(|x| x.sessions.iter())
+    }
+
+    /// The report as flat records: sessions, then days, docs, tags and the
+    /// total.
+    pub fn records(&self) -> Vec<Record> {
+        let mut output: Vec<Record> = self.sessions()
+            .map(|x| Record::Session(x.into()))
+            .collect();
+        output.extend(self.days.iter().map(|x| Record::Day(DayRecord {
+            date: x.date.format(DATE_FMT).to_string(),
+            seconds: x.total.num_seconds()
+        })));
+        output.extend(self.docs.iter().map(|x| Record::Doc(DocRecord {
+            path: x.doc.path.to_string(),
+            jira_key: x.doc.jira_key(),
+            seconds: x.total.num_seconds()
+        })));
+        output.extend(self.tags.iter().map(|x| Record::Tag(TagRecord {
+            tag: x.tag.to_string(),
+            seconds: x.total.num_seconds()
+        })));
+        output.push(Record::Total { seconds: self.total.num_seconds() });
+        output
+    }
+}
+
+/// A session as exported on machine-readable outputs.
+///
+/// Dates are `YYYY-MM-DD`, timestamps RFC 3339 in the offset the entry was
+/// logged with. `date` is the day the session (or this part of it) counts
+/// for.
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionRecord {
+    pub path: String,
+    pub jira_key: Option<String>,
+    pub tags: Vec<String>,
+    pub date: String,
+    pub start: String,
+    pub end: String,
+    pub seconds: i64,
+    pub note: Option<String>
+}
+
+impl<'a> From<&Session<'a>> for SessionRecord {
+    fn from(x: &Session<'a>) -> SessionRecord {
+        SessionRecord {
+            path: x.doc.path.to_string(),
+            jira_key: x.doc.jira_key(),
+            tags: x.doc.metadata.tags.clone(),
+            date: x.day.format(DATE_FMT).to_string(),
+            start: x.entry.start.to_rfc3339(),
+            end: x.entry.end.unwrap().to_rfc3339(),
+            seconds: x.duration().num_seconds(),
+            note: x.entry.note.clone()
+        }
+    }
+}
+
+/// Time spent on a day.
+#[derive(Serialize, Debug, Clone)]
+pub struct DayRecord {
+    pub date: String,
+    pub seconds: i64
+}
+
+/// Time spent on a doc.
+#[derive(Serialize, Debug, Clone)]
+pub struct DocRecord {
+    pub path: String,
+    pub jira_key: Option<String>,
+    pub seconds: i64
+}
+
+/// Time spent on docs with a tag.
+#[derive(Serialize, Debug, Clone)]
+pub struct TagRecord {
+    pub tag: String,
+    pub seconds: i64
+}
+
+/// Any record of a report, serialized with a `type` field telling them
+/// apart (`session`, `day`, `doc`, `tag` or `total`).
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Session(SessionRecord),
+    Day(DayRecord),
+    Doc(DocRecord),
+    Tag(TagRecord),
+    Total { seconds: i64 }
+}